@@ -0,0 +1,483 @@
+//! Rewriting the `//~` annotations of a test file so that they match the
+//! diagnostics that were actually emitted.
+
+use crate::{
+    diagnostics::{Level, Message},
+    display,
+    parser::{ErrorMatch, ErrorMatchKind},
+    per_test_config::TestConfig,
+    Error, Errors, ExpectedLine,
+};
+use spanned::Span;
+use std::num::NonZeroUsize;
+
+/// How an annotation picks the line it is matched against.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+    /// `//~`, the annotation's own line.
+    Plain,
+    /// `//~?`, any line.
+    Anywhere,
+    /// `//~^^`, a line above.
+    Above,
+    /// `//~vv`, a line below.
+    Below,
+    /// `//~|`, the same line as the neighbouring annotation of the chain.
+    Fallthrough,
+}
+
+/// A `//~` annotation as it will be written back to the test file.
+#[derive(Debug)]
+struct Annotation {
+    /// The span of the annotation's pattern or code, identifying it in the errors.
+    /// Dummy for annotations that get inserted.
+    span: Span,
+    /// The (zero-indexed) line of the annotation in the original file.
+    line: usize,
+    /// The (zero-indexed) line of the original file that the annotation matches against.
+    target: usize,
+    marker: Marker,
+    /// Everything between the `~` and the marker, i.e. the revisions.
+    revisions: String,
+    /// The level, code or pattern following the marker.
+    body: String,
+    /// The level of diagnostics the annotation matches.
+    level: Level,
+    /// Whether the annotation matches by diagnostic code instead of by message.
+    code_style: bool,
+    removed: bool,
+}
+
+/// A source line that holds an annotation, split into its parts.
+struct AnnotationLine<'a> {
+    /// Everything before the comment.
+    code: &'a str,
+    /// Everything between `~` and the marker.
+    revisions: &'a str,
+    marker: Marker,
+    body: &'a str,
+}
+
+impl<'a> AnnotationLine<'a> {
+    fn split(line: &'a str, comment_start: &str) -> Option<Self> {
+        let (code, rest) = line.split_once(comment_start)?;
+        let rest = rest.strip_prefix('~')?;
+        let revisions_len = match rest.strip_prefix('[') {
+            Some(revisions) => revisions.find(']')? + 2,
+            None => 0,
+        };
+        let (revisions, rest) = rest.split_at(revisions_len);
+        let (marker, len) = match rest.chars().next() {
+            Some('?') => (Marker::Anywhere, 1),
+            Some('|') => (Marker::Fallthrough, 1),
+            Some('^') => (
+                Marker::Above,
+                rest.len() - rest.trim_start_matches('^').len(),
+            ),
+            Some('v') => (
+                Marker::Below,
+                rest.len() - rest.trim_start_matches('v').len(),
+            ),
+            _ => (Marker::Plain, 0),
+        };
+        Some(Self {
+            code,
+            revisions,
+            marker,
+            body: rest[len..].trim(),
+        })
+    }
+}
+
+impl TestConfig {
+    /// Rewrites the `//~` annotations of the test file so that the errors that
+    /// [`TestConfig::check_annotations`] reported go away. All errors that were
+    /// resolved by rewriting the test file are removed from `errors`.
+    ///
+    /// Files with revisions are left alone, as all revisions share the same
+    /// file and are tested concurrently.
+    pub(crate) fn bless_annotations(&self, errors: &mut Errors) {
+        if errors.is_empty() {
+            return;
+        }
+        if self.comments.revisions.is_some() {
+            errors.push(Error::ConfigError(format!(
+                "`{}` has revisions, so its annotations can't be blessed automatically",
+                display(self.status.path())
+            )));
+            return;
+        }
+        let path = self.status.path();
+        let Ok(source) = std::fs::read_to_string(path) else {
+            return;
+        };
+        let comment_start = self.config.comment_start;
+        let diagnostic_code_prefix = self
+            .comments()
+            .find_map(|r| r.diagnostic_code_prefix.as_ref())
+            .map(|prefix| prefix.content.clone())
+            .unwrap_or_default();
+
+        let lines: Vec<&str> = source.split_inclusive('\n').collect();
+        let line_of = |span: &Span| {
+            let mut start = 0;
+            lines
+                .iter()
+                .position(|line| {
+                    start += line.len();
+                    span.bytes.start < start
+                })
+                .unwrap_or(lines.len())
+        };
+
+        let error_matches: Vec<&ErrorMatch> = self
+            .comments()
+            .flat_map(|r| r.error_matches.iter())
+            .collect();
+        let span_of = |m: &ErrorMatch| match &m.kind {
+            ErrorMatchKind::Pattern { pattern, .. } => pattern.span(),
+            ErrorMatchKind::Code { code, .. } => code.span(),
+        };
+        let mut annotations: Vec<Annotation> = error_matches
+            .iter()
+            .filter_map(|m| {
+                let span = span_of(m);
+                // Annotations from other files can't be rewritten.
+                if span.file != path {
+                    return None;
+                }
+                let line = line_of(&span);
+                let split = AnnotationLine::split(lines.get(line)?, comment_start)?;
+                let (level, code_style) = match m.kind {
+                    ErrorMatchKind::Pattern { level, .. } => (level, false),
+                    ErrorMatchKind::Code { level, .. } => (level, true),
+                };
+                Some(Annotation {
+                    span,
+                    line,
+                    target: if m.anywhere { line } else { m.line.get() - 1 },
+                    marker: split.marker,
+                    revisions: split.revisions.to_string(),
+                    body: split.body.to_string(),
+                    level,
+                    code_style,
+                    removed: false,
+                })
+            })
+            .collect();
+        let find_annotation = |annotations: &[Annotation], span: &Span| {
+            annotations.iter().position(|a| a.span == *span)
+        };
+
+        // Use codes for new annotations if that is all that the test uses so far.
+        let prefer_codes = !error_matches.is_empty()
+            && error_matches
+                .iter()
+                .all(|m| matches!(m.kind, ErrorMatchKind::Code { .. }));
+        let body_for = |msg: &Message, code_style: bool| {
+            let code = msg
+                .code
+                .as_deref()
+                .and_then(|code| code.strip_prefix(&diagnostic_code_prefix));
+            // `failure-note`s are never annotated.
+            let level_name = (msg.level != Level::FailureNote).then(|| msg.level.annotation_name());
+            match (code, level_name) {
+                (Some(code), _) if code_style && msg.level == Level::Error => {
+                    Some(code.to_string())
+                }
+                (Some(code), Some(level)) if code_style => Some(format!("{level} {code}")),
+                (_, Some(level)) => {
+                    let message = msg.message.lines().next().unwrap_or_default().trim();
+                    (!message.is_empty()).then(|| format!("{level}: {message}"))
+                }
+                (_, None) => None,
+            }
+        };
+
+        // All diagnostics that no annotation matched, by their (zero-indexed) line.
+        let mut unmatched: Vec<(usize, Message)> = vec![];
+        errors.retain_mut(|error| match error {
+            Error::ErrorsWithoutPattern {
+                path: Some((_, line)),
+                msgs,
+            } if line.get() <= lines.len() => {
+                let line = line.get() - 1;
+                unmatched.extend(std::mem::take(msgs).into_iter().map(|msg| (line, msg)));
+                false
+            }
+            _ => true,
+        });
+
+        let mut changed = false;
+        errors.retain(|error| {
+            let (span, expected_line, near_misses) = match error {
+                Error::PatternNotFound {
+                    pattern,
+                    expected_line,
+                    near_misses,
+                } => (pattern.span(), *expected_line, &near_misses[..]),
+                Error::CodeNotFound {
+                    code,
+                    expected_line,
+                } => (code.span(), *expected_line, &[][..]),
+                Error::WrongMatchCount {
+                    span,
+                    expected,
+                    actual,
+                    ..
+                } => {
+                    let Some(idx) = find_annotation(&annotations, span) else {
+                        return true;
+                    };
+                    let annotation = &mut annotations[idx];
+                    let old = format!("[x{expected}]");
+                    let new = match actual {
+                        0 => {
+                            annotation.removed = true;
+                            changed = true;
+                            return false;
+                        }
+                        1 => String::new(),
+                        n => format!("[x{n}]"),
+                    };
+                    annotation.body = annotation.body.replacen(&old, &new, 1);
+                    changed = true;
+                    return false;
+                }
+                _ => return true,
+            };
+            let Some(idx) = find_annotation(&annotations, &span) else {
+                return true;
+            };
+            let Annotation {
+                level, code_style, ..
+            } = annotations[idx];
+            // Prefer the diagnostics that look like what the annotation was meant to
+            // match, and otherwise any diagnostic of the same level on the expected line.
+            // Relative annotations get moved to the line of the diagnostic they now match,
+            // `//~` annotations have to stay on their line.
+            let line = match expected_line {
+                ExpectedLine::Line(line) => Some(line.get() - 1),
+                ExpectedLine::Anywhere => None,
+                ExpectedLine::OtherFile => return true,
+            };
+            let movable = matches!(
+                annotations[idx].marker,
+                Marker::Above | Marker::Below | Marker::Fallthrough
+            );
+            let replacement = near_misses
+                .iter()
+                .find_map(|(near_line, near_level, near_miss)| {
+                    unmatched.iter().position(|(l, msg)| {
+                        *l == near_line.get() - 1
+                            && msg.level == *near_level
+                            && msg.message == *near_miss
+                            && (movable || line.is_none() || line == Some(*l))
+                    })
+                })
+                .or_else(|| {
+                    unmatched.iter().position(|(l, msg)| {
+                        line == Some(*l)
+                            && msg.level == level
+                            && body_for(msg, code_style).is_some()
+                    })
+                });
+            let annotation = &mut annotations[idx];
+            match replacement.and_then(|i| Some((i, body_for(&unmatched[i].1, code_style)?))) {
+                Some((i, body)) => {
+                    let (l, _) = unmatched.remove(i);
+                    if movable {
+                        annotation.target = l;
+                    }
+                    annotation.body = body;
+                }
+                None => annotation.removed = true,
+            }
+            changed = true;
+            false
+        });
+
+        // Annotate all remaining diagnostics, on their own line if possible.
+        let mut inserted: Vec<Annotation> = vec![];
+        let mut appended: Vec<Option<String>> = vec![None; lines.len()];
+        for (line, msg) in unmatched {
+            let Some(body) = body_for(&msg, prefer_codes) else {
+                errors.push(Error::ErrorsWithoutPattern {
+                    path: Some((path.to_path_buf(), NonZeroUsize::new(line + 1).unwrap())),
+                    msgs: vec![msg],
+                });
+                continue;
+            };
+            changed = true;
+            let free = !lines[line].contains(comment_start)
+                && !lines[line].trim().is_empty()
+                && appended[line].is_none();
+            if free {
+                appended[line] = Some(body);
+                continue;
+            }
+            // Put the annotation below any `//~^` chain that already points at the line.
+            let mut after = line;
+            while lines
+                .get(after + 1)
+                .and_then(|l| AnnotationLine::split(l, comment_start))
+                .is_some_and(|l| {
+                    l.code.trim().is_empty()
+                        && matches!(l.marker, Marker::Above | Marker::Fallthrough)
+                })
+            {
+                after += 1;
+            }
+            inserted.push(Annotation {
+                span: Span::default(),
+                line: after,
+                target: line,
+                marker: Marker::Above,
+                revisions: String::new(),
+                body,
+                level: msg.level,
+                code_style: prefer_codes,
+                removed: false,
+            });
+        }
+        if !inserted.is_empty() || appended.iter().any(Option::is_some) {
+            errors.retain(|error| !matches!(error, Error::NoPatternsFound));
+        }
+        if !changed {
+            return;
+        }
+
+        let rewritten = rewrite(&lines, comment_start, annotations, inserted, appended);
+        if let Err(err) = std::fs::write(path, rewritten) {
+            errors.push(Error::ConfigError(format!(
+                "failed to write blessed annotations to `{}`: {err}",
+                display(path)
+            )));
+        }
+    }
+}
+
+/// Produces the new file contents, keeping all relative annotations pointing
+/// at their lines even though lines were inserted or removed.
+fn rewrite(
+    lines: &[&str],
+    comment_start: &str,
+    annotations: Vec<Annotation>,
+    mut inserted: Vec<Annotation>,
+    appended: Vec<Option<String>>,
+) -> String {
+    let mut by_line: Vec<Option<Annotation>> = lines.iter().map(|_| None).collect();
+    for annotation in annotations {
+        let slot = &mut by_line[annotation.line];
+        // Only one annotation fits on a line, the others go below it.
+        let extra = match slot {
+            Some(first) if first.removed => slot.replace(annotation),
+            Some(_) => Some(annotation),
+            None => slot.replace(annotation),
+        };
+        if let Some(mut extra) = extra.filter(|a| !a.removed) {
+            if extra.marker == Marker::Plain {
+                extra.marker = Marker::Above;
+            }
+            inserted.push(extra);
+        }
+    }
+    inserted.sort_by_key(|a| (a.line, a.target));
+
+    // Lay out all lines, and remember where each original line ended up.
+    enum Out<'a> {
+        Original(usize, &'a str),
+        Inserted(Annotation),
+    }
+    let mut out = vec![];
+    let mut new_line = vec![None; lines.len()];
+    let mut inserted = inserted.into_iter().peekable();
+    for (i, &line) in lines.iter().enumerate() {
+        let removed_comment_line = by_line[i].as_ref().is_some_and(|a| {
+            a.removed
+                && AnnotationLine::split(line, comment_start)
+                    .is_some_and(|l| l.code.trim().is_empty())
+        });
+        if !removed_comment_line {
+            new_line[i] = Some(out.len());
+            out.push(Out::Original(i, line));
+        }
+        while let Some(annotation) = inserted.next_if(|a| a.line == i) {
+            out.push(Out::Inserted(annotation));
+        }
+    }
+
+    let target_of = |a: &Annotation| new_line[a.target];
+    let mut result = String::new();
+    // The target of the annotation on the previous output line, if it can be continued with `//~|`.
+    let mut chain: Option<usize> = None;
+    for (idx, out_line) in out.iter().enumerate() {
+        let (annotation, code, ending) = match out_line {
+            Out::Original(i, line) => {
+                let (content, ending) = split_ending(line);
+                let annotation = by_line[*i].as_ref();
+                match (annotation, AnnotationLine::split(content, comment_start)) {
+                    (Some(annotation), Some(split)) if !annotation.removed => {
+                        (Some(annotation), split.code.to_string(), ending)
+                    }
+                    (Some(_), Some(split)) => {
+                        result.push_str(split.code.trim_end());
+                        result.push_str(ending);
+                        chain = None;
+                        continue;
+                    }
+                    _ => {
+                        result.push_str(content);
+                        if let Some(body) = &appended[*i] {
+                            result.push_str(&format!(" {comment_start}~ {body}"));
+                        }
+                        result.push_str(ending);
+                        chain = None;
+                        continue;
+                    }
+                }
+            }
+            Out::Inserted(annotation) => {
+                if !result.is_empty() && !result.ends_with('\n') {
+                    result.push('\n');
+                }
+                let target = lines[annotation.target];
+                let indent = &target[..target.len() - target.trim_start().len()];
+                (Some(annotation), indent.to_string(), "\n")
+            }
+        };
+        let annotation = annotation.unwrap();
+        let marker = match (annotation.marker, target_of(annotation)) {
+            (Marker::Plain, _) => String::new(),
+            (Marker::Anywhere, _) => "?".into(),
+            (_, Some(target)) if chain == Some(target) && target < idx => "|".into(),
+            (_, Some(target)) if target < idx => "^".repeat(idx - target),
+            (_, Some(target)) if target > idx => "v".repeat(target - idx),
+            // The target line is gone, leave the annotation as it was.
+            (marker, _) => match marker {
+                Marker::Fallthrough => "|".into(),
+                _ => String::new(),
+            },
+        };
+        // Keep existing `//~|` annotations of `//~v` chains as they were.
+        let marker = match (annotation.marker, target_of(annotation)) {
+            (Marker::Fallthrough, Some(target)) if target > idx => "|".into(),
+            _ => marker,
+        };
+        chain = match annotation.marker {
+            Marker::Plain | Marker::Anywhere => None,
+            _ => target_of(annotation),
+        };
+        result.push_str(&format!(
+            "{code}{comment_start}~{}{marker} {}",
+            annotation.revisions, annotation.body
+        ));
+        result.push_str(ending);
+    }
+    result
+}
+
+fn split_ending(line: &str) -> (&str, &str) {
+    let content = line.trim_end_matches(['\n', '\r']);
+    (content, &line[content.len()..])
+}
@@ -0,0 +1,134 @@
+//! Strategies for deciding whether a test's output matches its output file.
+
+use bstr::ByteSlice;
+
+/// Decides whether the actual output (after normalization) matches the expected
+/// output read from the output file. Called with `(actual, expected)`.
+pub type Comparison = fn(&[u8], &[u8]) -> bool;
+
+/// The built-in comparisons usable with `//@compare-stderr: name` and similar.
+/// Additional ones can be registered in [`Config::comparisons`](crate::Config::comparisons).
+pub const BUILTIN_COMPARISONS: &[(&str, Comparison)] = &[
+    ("exact", exact),
+    ("wildcards", wildcards),
+    ("trailing-whitespace", trailing_whitespace),
+    ("unordered", unordered),
+    ("subset", subset),
+];
+
+/// The outputs must be equal, except for wildcards in the expected output:
+/// `[..]` matches any text within a line, and a line consisting of just `...`
+/// matches any number of lines.
+pub fn wildcards(actual: &[u8], expected: &[u8]) -> bool {
+    if actual == expected {
+        return true;
+    }
+    let actual = lines(actual);
+    let expected = lines(expected);
+    // `matches[e][a]`: whether `expected[e..]` matches `actual[a..]`
+    let mut matches = vec![vec![false; actual.len() + 1]; expected.len() + 1];
+    matches[expected.len()][actual.len()] = true;
+    for e in (0..expected.len()).rev() {
+        for a in (0..=actual.len()).rev() {
+            matches[e][a] = if expected[e] == b"..." {
+                matches[e + 1][a] || (a < actual.len() && matches[e][a + 1])
+            } else {
+                a < actual.len() && line_matches(actual[a], expected[e]) && matches[e + 1][a + 1]
+            };
+        }
+    }
+    matches[0][0]
+}
+
+/// Produces the new contents of an output file when blessing: the actual output, but
+/// with the wildcards of the `expected` output kept as long as the output still matches them.
+/// Everything after the first line that doesn't match is replaced by the actual output.
+pub fn preserve_wildcards(actual: &[u8], expected: &[u8]) -> Vec<u8> {
+    let actual = lines(actual);
+    let expected = lines(expected);
+    let mut blessed: Vec<&[u8]> = vec![];
+    let (mut a, mut e) = (0, 0);
+    while a < actual.len() && e < expected.len() {
+        if expected[e] == b"..." {
+            let skip = match &expected[e + 1..] {
+                [] => Some(actual.len() - a),
+                // `...` in the last line of a file ending in a newline
+                [b""] => Some(actual.len() - a - 1),
+                [next, ..] => actual[a..].iter().position(|line| line_matches(line, next)),
+            };
+            let Some(skip) = skip else { break };
+            a += skip;
+        } else if line_matches(actual[a], expected[e]) {
+            a += 1;
+        } else {
+            break;
+        }
+        blessed.push(expected[e]);
+        e += 1;
+    }
+    blessed.extend(&actual[a..]);
+    blessed.join(&b"\n"[..])
+}
+
+fn lines(text: &[u8]) -> Vec<&[u8]> {
+    text.split(|&b| b == b'\n').collect()
+}
+
+/// Whether `line` matches `pattern`, where `[..]` in `pattern` matches any text.
+fn line_matches(line: &[u8], pattern: &[u8]) -> bool {
+    let mut parts: Vec<_> = pattern.split_str("[..]").collect();
+    let first = parts.remove(0);
+    let Some(mut rest) = line.strip_prefix(first) else {
+        return false;
+    };
+    let Some(last) = parts.pop() else {
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(pos) => rest = &rest[pos + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// The outputs must be byte-for-byte equal. This is the default.
+pub fn exact(actual: &[u8], expected: &[u8]) -> bool {
+    actual == expected
+}
+
+/// Whitespace at the end of lines and empty lines at the end of the output are ignored.
+pub fn trailing_whitespace(actual: &[u8], expected: &[u8]) -> bool {
+    fn lines(text: &[u8]) -> Vec<&[u8]> {
+        let mut lines: Vec<_> = text.lines().map(|line| line.trim_end()).collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+    lines(actual) == lines(expected)
+}
+
+/// The outputs must contain the same lines, but in any order.
+/// Useful when diagnostics are emitted in a nondeterministic order.
+pub fn unordered(actual: &[u8], expected: &[u8]) -> bool {
+    let mut actual: Vec<_> = actual.lines().collect();
+    let mut expected: Vec<_> = expected.lines().collect();
+    actual.sort_unstable();
+    expected.sort_unstable();
+    actual == expected
+}
+
+/// Every line of the expected output must occur in the actual output,
+/// in any order. The actual output may contain additional lines.
+pub fn subset(actual: &[u8], expected: &[u8]) -> bool {
+    let mut actual: Vec<_> = actual.lines().collect();
+    expected.lines().all(|line| {
+        actual
+            .iter()
+            .position(|&l| l == line)
+            .map(|pos| actual.swap_remove(pos))
+            .is_some()
+    })
+}
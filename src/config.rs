@@ -1,18 +1,28 @@
 #[cfg(feature = "rustc")]
 use crate::{
-    aux_builds::AuxBuilder, custom_flags::edition::Edition,
-    custom_flags::revision_args::RustcRevisionArgs, custom_flags::run::Run,
-    custom_flags::rustfix::RustfixMode, custom_flags::Flag, filter::Match,
+    aux_builds::AuxBuilder,
+    custom_flags::edition::Edition,
+    custom_flags::json_snapshot::JsonSnapshot,
+    custom_flags::needs::{NeedsEnv, NeedsFeature, NeedsProgram},
+    custom_flags::revision_args::RustcRevisionArgs,
+    custom_flags::run::Run,
+    custom_flags::rustfix::RustfixMode,
+    custom_flags::Flag,
 };
 use crate::{
+    compare::{self, Comparison, BUILTIN_COMPARISONS},
     diagnostics::{self, Diagnostics},
+    filter::{Match, BUILTIN_FILTERS},
     parser::CommandParserFunc,
     per_test_config::{Comments, Condition, TestConfig},
+    test_result::IgnoreReason,
     CommandBuilder, Error, Errored, Errors,
 };
-use color_eyre::eyre::Result;
+use color_eyre::eyre::{bail, Result};
 use regex::bytes::Regex;
-use spanned::Spanned;
+use spanned::{Span, Spanned};
+#[cfg(feature = "rustc")]
+use std::sync::OnceLock;
 use std::{
     collections::BTreeMap,
     num::NonZeroUsize,
@@ -22,7 +32,8 @@ use std::{
 };
 
 mod args;
-pub use args::{Args, Format};
+mod file;
+pub use args::{Args, DiffFormat, Format};
 
 #[derive(Debug, Clone)]
 /// Central datastructure containing all information to run the tests.
@@ -31,6 +42,8 @@ pub struct Config {
     pub host: Option<String>,
     /// `None` to run on the host, otherwise a target triple
     pub target: Option<String>,
+    /// Version and release channel of the compiler; usually will be auto-detected.
+    pub compiler_version: Option<rustc_version::VersionMeta>,
     /// The folder in which to start searching for .rs files
     pub root_dir: PathBuf,
     /// The binary to actually execute.
@@ -39,6 +52,12 @@ pub struct Config {
     pub output_conflict_handling: OutputConflictHandling,
     /// The recommended command to bless failing tests.
     pub bless_command: Option<String>,
+    /// Rewrite the `//~` annotations in test files to match the actual diagnostics
+    /// instead of reporting mismatches.
+    pub bless_annotations: bool,
+    /// Record which normalization rules replaced something and report them,
+    /// and the rules that matched nothing, when the output differs.
+    pub trace_normalization: bool,
     /// Where to dump files like the binaries compiled from tests.
     /// Defaults to `target/ui/index_of_config` in the current directory.
     pub out_dir: PathBuf,
@@ -60,6 +79,17 @@ pub struct Config {
     pub comment_start: &'static str,
     /// Custom comment parsers
     pub custom_comments: BTreeMap<&'static str, CommandParserFunc>,
+    /// Comparison strategies that tests can select by name via `//@compare-stderr: name`
+    /// and similar. Starts out with the [`BUILTIN_COMPARISONS`].
+    pub comparisons: BTreeMap<&'static str, Comparison>,
+    /// The output kinds that `//@normalize-{kind}` and `//@compare-{kind}` accept, in addition
+    /// to the `N.fixed` outputs of rustfix. Custom flags that check outputs of their own kind via
+    /// [`TestConfig::check_output`] should add it here.
+    pub output_kinds: Vec<&'static str>,
+    /// The `cfg`s of the target, used to evaluate `//@ignore-cfg: EXPR` and `//@only-cfg: EXPR`.
+    /// Queried from the program when the first test uses them.
+    #[cfg(feature = "rustc")]
+    pub target_cfgs: TargetCfgs,
     /// Custom diagnostic extractor (invoked on the output of tests)
     pub diagnostic_extractor: fn(&Path, &[u8]) -> Diagnostics,
     /// Handle to the global abort check.
@@ -84,6 +114,50 @@ impl AbortCheck {
     }
 }
 
+/// The `cfg`s of the target, queried at most once and shared between clones of a [`Config`].
+#[cfg(feature = "rustc")]
+#[derive(Clone, Debug, Default)]
+pub struct TargetCfgs(Arc<OnceLock<Result<Vec<cargo_platform::Cfg>, String>>>);
+
+#[cfg(feature = "rustc")]
+impl TargetCfgs {
+    /// Use the given `cfg`s instead of querying them from the program.
+    pub fn new(cfgs: Vec<cargo_platform::Cfg>) -> Self {
+        Self(Arc::new(OnceLock::from(Ok(cfgs))))
+    }
+
+    fn get(&self, config: &Config) -> Result<&[cargo_platform::Cfg], &str> {
+        self.0
+            .get_or_init(|| {
+                if config.program.cfg_flag.is_none() {
+                    return Err(format!(
+                        "`{}` does not report the cfgs of the target",
+                        config.program.display()
+                    ));
+                }
+                crate::dependencies::cfgs(config).map_err(|err| {
+                    format!(
+                        "failed to query the cfgs of the target with `{}`:\n{}",
+                        err.command,
+                        String::from_utf8_lossy(&err.stderr)
+                    )
+                })
+            })
+            .as_deref()
+            .map_err(|err| &**err)
+    }
+}
+
+/// The output kinds that `ui_test` itself produces.
+const OUTPUT_KINDS: &[&str] = &[
+    "stderr",
+    "stdout",
+    "fixed",
+    "run.stderr",
+    "run.stdout",
+    "json",
+];
+
 /// Function that performs the actual output conflict handling.
 pub type OutputConflictHandling = fn(&Path, &[u8], &mut Errors, &TestConfig);
 
@@ -95,10 +169,13 @@ impl Config {
         Self {
             host: Default::default(),
             target: Default::default(),
+            compiler_version: Default::default(),
             root_dir: Default::default(),
             program: CommandBuilder::cmd(""),
             output_conflict_handling: error_on_output_conflict,
             bless_command: Default::default(),
+            bless_annotations: false,
+            trace_normalization: false,
             out_dir: Default::default(),
             skip_files: Default::default(),
             filter_files: Default::default(),
@@ -109,6 +186,10 @@ impl Config {
             comment_defaults,
             comment_start: "//",
             custom_comments: Default::default(),
+            comparisons: BUILTIN_COMPARISONS.iter().copied().collect(),
+            output_kinds: OUTPUT_KINDS.to_vec(),
+            #[cfg(feature = "rustc")]
+            target_cfgs: Default::default(),
             diagnostic_extractor: diagnostics::default_diagnostics_extractor,
             abort_check: Default::default(),
         }
@@ -135,7 +216,7 @@ impl Config {
                 config: &Config,
                 _comments: &Comments,
                 _revision: &str,
-            ) -> bool {
+            ) -> Result<bool, Errored> {
                 let target = config.target.as_ref().unwrap();
                 static ASM_SUPPORTED_ARCHS: &[&str] = &[
                     "x86", "x86_64", "arm", "aarch64", "riscv32",
@@ -143,7 +224,7 @@ impl Config {
                     // These targets require an additional asm_experimental_arch feature.
                     // "nvptx64", "hexagon", "mips", "mips64", "spirv", "wasm32",
                 ];
-                !ASM_SUPPORTED_ARCHS.iter().any(|arch| target.contains(arch))
+                Ok(!ASM_SUPPORTED_ARCHS.iter().any(|arch| target.contains(arch)))
             }
         }
 
@@ -173,10 +254,13 @@ impl Config {
         let mut config = Self {
             host: None,
             target: None,
+            compiler_version: None,
             root_dir: root_dir.into(),
             program: CommandBuilder::rustc(),
             output_conflict_handling: error_on_output_conflict,
             bless_command: None,
+            bless_annotations: false,
+            trace_normalization: false,
             out_dir: std::env::var_os("CARGO_TARGET_DIR")
                 .map(PathBuf::from)
                 .unwrap_or_else(|| std::env::current_dir().unwrap().join("target"))
@@ -190,6 +274,10 @@ impl Config {
             comment_defaults,
             comment_start: "//",
             custom_comments: Default::default(),
+            comparisons: BUILTIN_COMPARISONS.iter().copied().collect(),
+            output_kinds: OUTPUT_KINDS.to_vec(),
+            #[cfg(feature = "rustc")]
+            target_cfgs: Default::default(),
             diagnostic_extractor: diagnostics::rustc::rustc_diagnostics_extractor,
             abort_check: Default::default(),
         };
@@ -206,6 +294,13 @@ impl Config {
                 parser.set_custom_once("edition", Edition((*args).into()), args.span());
             });
 
+        config
+            .custom_comments
+            .insert("json-snapshot", |parser, _args, span| {
+                // args are ignored (can be used as comment)
+                parser.set_custom_once("json-snapshot", JsonSnapshot, span);
+            });
+
         config
             .custom_comments
             .insert("needs-asm-support", |parser, _args, span| {
@@ -213,6 +308,47 @@ impl Config {
                 parser.set_custom_once("needs-asm-support", NeedsAsmSupport, span);
             });
 
+        config
+            .custom_comments
+            .insert("needs-env", |parser, args, span| {
+                parser.check(
+                    span,
+                    !args.is_empty(),
+                    "`needs-env` requires a variable name",
+                );
+                parser.add_custom_spanned("needs-env", NeedsEnv((*args).into()), args.span());
+            });
+
+        config
+            .custom_comments
+            .insert("needs-program", |parser, args, span| {
+                parser.check(
+                    span,
+                    !args.is_empty(),
+                    "`needs-program` requires a program name",
+                );
+                parser.add_custom_spanned(
+                    "needs-program",
+                    NeedsProgram((*args).into()),
+                    args.span(),
+                );
+            });
+
+        config
+            .custom_comments
+            .insert("needs-feature", |parser, args, span| {
+                parser.check(
+                    span,
+                    !args.is_empty(),
+                    "`needs-feature` requires a feature name",
+                );
+                parser.add_custom_spanned(
+                    "needs-feature",
+                    NeedsFeature((*args).into()),
+                    args.span(),
+                );
+            });
+
         config.custom_comments.insert("run", |parser, args, span| {
             let set = |exit_code| {
                 parser.set_custom_once(
@@ -278,10 +414,16 @@ impl Config {
             ref filters,
             check,
             bless,
+            bless_annotations,
+            trace_normalization,
             list,
             exact,
             ignored,
+            show_ignored_reasons: _,
             format: _,
+            diff: _,
+            diff_context: _,
+            diff_tool: _,
             threads,
             ref skip,
         } = *args;
@@ -294,6 +436,8 @@ impl Config {
         self.filter_exact = exact;
 
         self.list = list;
+        self.bless_annotations = bless_annotations;
+        self.trace_normalization = trace_normalization;
 
         if check {
             self.output_conflict_handling = error_on_output_conflict;
@@ -370,21 +514,68 @@ impl Config {
         ));
     }
 
-    /// Make sure we have the host and target triples.
+    /// Replace all occurrences of a regex pattern in outputs of the given kind with a byte string.
+    /// Kinds are `stderr`, `stdout`, `fixed`, `run.stderr`, `run.stdout` or any kind that
+    /// custom flags pass to [`TestConfig::check_output`]. Filters for a kind also apply to
+    /// all kinds ending in `.{kind}`, so `stderr` filters also apply to `run.stderr`.
+    #[track_caller]
+    pub fn output_filter(
+        &mut self,
+        kind: &str,
+        pattern: &str,
+        replacement: &'static (impl AsRef<[u8]> + ?Sized),
+    ) {
+        self.comment_defaults.base().normalizations_mut(kind).push((
+            Regex::new(pattern).unwrap().into(),
+            replacement.as_ref().to_owned(),
+        ));
+    }
+
+    /// Compare outputs of the given kind with `comparison` instead of requiring them to be
+    /// equal to the output file. Tests can still pick another comparison via
+    /// `//@compare-{kind}: name`. Like filters, comparisons for a kind also apply to
+    /// all kinds ending in `.{kind}`.
+    pub fn output_comparison(&mut self, kind: &str, comparison: Comparison) {
+        self.comment_defaults
+            .base()
+            .compare
+            .insert(kind.to_owned(), Spanned::dummy(comparison));
+    }
+
+    /// Enable a built-in normalization (see [`BUILTIN_FILTERS`](crate::filter::BUILTIN_FILTERS))
+    /// for stderr and stdout, like `//@normalize: name` does for a single test.
+    /// Errors if there is no built-in normalization called `name`.
+    pub fn builtin_filter(&mut self, name: &str) -> Result<()> {
+        let Some((filter, replacement)) = Match::builtin(name) else {
+            bail!("`{name}` is not a built-in normalization, expected one of {BUILTIN_FILTERS:?}")
+        };
+        let base = self.comment_defaults.base();
+        base.normalize_stderr
+            .push((filter.clone(), replacement.to_vec()));
+        base.normalize_stdout.push((filter, replacement.to_vec()));
+        Ok(())
+    }
+
+    /// Make sure we have the host and target triples, and the compiler version.
     pub fn fill_host_and_target(&mut self) -> Result<()> {
-        if self.host.is_none() {
-            self.host = Some(
-                rustc_version::VersionMeta::for_command(std::process::Command::new(
-                    &self.program.program,
-                ))
-                .map_err(|err| {
-                    color_eyre::eyre::Report::new(err).wrap_err(format!(
+        if self.host.is_none() || self.compiler_version.is_none() {
+            match rustc_version::VersionMeta::for_command(std::process::Command::new(
+                &self.program.program,
+            )) {
+                Ok(version) => {
+                    self.host.get_or_insert_with(|| version.host.clone());
+                    self.compiler_version = Some(version);
+                }
+                Err(err) if self.host.is_none() => {
+                    return Err(color_eyre::eyre::Report::new(err).wrap_err(format!(
                         "failed to parse rustc version info: {}",
                         self.program.display().to_string().replace('\\', "/")
-                    ))
-                })?
-                .host,
-            );
+                    )))
+                }
+                // The host was set explicitly, so the program may not report a version at all.
+                // Tests with version conditions report an error in that case.
+                Err(_) => {}
+            }
         }
         if self.target.is_none() {
             self.target = Some(self.host.clone().unwrap());
@@ -419,40 +610,115 @@ impl Config {
         get_pointer_width(self.target.as_ref().unwrap())
     }
 
-    pub(crate) fn test_condition(&self, condition: &Condition) -> bool {
+    pub(crate) fn test_condition(&self, condition: &Condition) -> Result<bool, Errored> {
         let target = self.target.as_ref().unwrap();
-        match condition {
+        Ok(match condition {
             Condition::Bitwidth(bits) => bits.iter().any(|bits| self.get_pointer_width() == *bits),
             Condition::Target(t) => t.iter().any(|t| target.contains(&**t)),
             Condition::Host(t) => t.iter().any(|t| self.host.as_ref().unwrap().contains(&**t)),
             Condition::OnHost => self.host_matches_target(),
-        }
+            Condition::Channel(channel) => self.compiler_version(condition)?.channel == *channel,
+            Condition::MinRustcVersion(min) => {
+                release(&self.compiler_version(condition)?.semver) >= release(min)
+            }
+            Condition::MaxRustcVersion(max) => {
+                release(&self.compiler_version(condition)?.semver) <= release(max)
+            }
+            #[cfg(feature = "rustc")]
+            Condition::Cfg(expr) => {
+                let cfgs = self.target_cfgs.get(self).map_err(|err| {
+                    Errored::new(
+                        vec![Error::ConfigError(format!(
+                            "cannot evaluate `cfg({expr})`: {err}"
+                        ))],
+                        "evaluating cfg condition",
+                    )
+                })?;
+                expr.matches(cfgs)
+            }
+        })
+    }
+
+    fn compiler_version(
+        &self,
+        condition: &Condition,
+    ) -> Result<&rustc_version::VersionMeta, Errored> {
+        self.compiler_version.as_ref().ok_or_else(|| {
+            Errored::new(
+                vec![Error::ConfigError(format!(
+                    "cannot evaluate `{condition}`: the version of `{}` is unknown",
+                    self.program.display()
+                ))],
+                "evaluating version condition",
+            )
+        })
     }
 
     /// Returns whether according to the in-file conditions, this file should be run.
+    /// Returns `false` if the conditions cannot be evaluated.
     pub fn test_file_conditions(&self, comments: &Comments, revision: &str) -> bool {
-        if comments
-            .for_revision(revision)
-            .flat_map(|r| r.ignore.iter())
-            .any(|c| self.test_condition(c))
-        {
-            return self.run_only_ignored;
+        matches!(self.ignore_reason(comments, revision), Ok(None))
+    }
+
+    /// Returns the rule that causes this file to be ignored according to the
+    /// in-file conditions, or `None` if it should be run.
+    pub fn ignore_reason(
+        &self,
+        comments: &Comments,
+        revision: &str,
+    ) -> Result<Option<IgnoreReason>, Errored> {
+        Ok(
+            match (
+                self.ignore_reason_inner(comments, revision)?,
+                self.run_only_ignored,
+            ) {
+                (reason, false) => reason,
+                (Some(_), true) => None,
+                (None, true) => Some(IgnoreReason::NotIgnored),
+            },
+        )
+    }
+
+    fn ignore_reason_inner(
+        &self,
+        comments: &Comments,
+        revision: &str,
+    ) -> Result<Option<IgnoreReason>, Errored> {
+        // Conditions added to `Revisioned` from code have no span.
+        let spanned = |cond: &Condition, spans: &[Span], i: usize| {
+            Spanned::new(cond.clone(), spans.get(i).cloned().unwrap_or_default())
+        };
+        for r in comments.for_revision(revision) {
+            for (i, cond) in r.ignore.iter().enumerate() {
+                if self.test_condition(cond)? {
+                    return Ok(Some(IgnoreReason::Ignore(spanned(
+                        cond,
+                        &r.ignore_spans,
+                        i,
+                    ))));
+                }
+            }
         }
-        if comments.for_revision(revision).any(|r| {
-            r.custom.values().any(|flags| {
-                flags
-                    .content
-                    .iter()
-                    .any(|flag| flag.test_condition(self, comments, revision))
-            })
-        }) {
-            return self.run_only_ignored;
+        for r in comments.for_revision(revision) {
+            for (&name, flags) in &r.custom {
+                for flag in &flags.content {
+                    if flag.test_condition(self, comments, revision)? {
+                        return Ok(Some(IgnoreReason::Flag {
+                            name,
+                            span: flags.span(),
+                        }));
+                    }
+                }
+            }
         }
-        comments
-            .for_revision(revision)
-            .flat_map(|r| r.only.iter())
-            .all(|c| self.test_condition(c))
-            ^ self.run_only_ignored
+        for r in comments.for_revision(revision) {
+            for (i, cond) in r.only.iter().enumerate() {
+                if !self.test_condition(cond)? {
+                    return Ok(Some(IgnoreReason::Only(spanned(cond, &r.only_spans, i))));
+                }
+            }
+        }
+        Ok(None)
     }
 
     pub(crate) fn aborted(&self) -> Result<(), Errored> {
@@ -479,6 +745,12 @@ impl Config {
     }
 }
 
+/// The release a compiler version belongs to. Pre-release versions like
+/// `1.80.0-nightly` count as the release they will become.
+fn release(version: &rustc_version::Version) -> (u64, u64, u64) {
+    (version.major, version.minor, version.patch)
+}
+
 /// Fail the test when mismatches are found, if provided the command string
 /// in [`Config::bless_command`] will be suggested as a way to bless the
 /// test.
@@ -488,15 +760,21 @@ pub fn error_on_output_conflict(
     errors: &mut Errors,
     config: &TestConfig,
 ) {
-    let normalized = config.normalize(output, &path.extension().unwrap().to_string_lossy());
+    let kind = config.output_kind(path);
+    let (normalized, normalization) = if config.config.trace_normalization {
+        config.normalize_traced(output, &kind)
+    } else {
+        (config.normalize(output, &kind), vec![])
+    };
     let expected = std::fs::read(path).unwrap_or_default();
-    if normalized != expected {
+    if !config.comparison(&kind)(&normalized, &expected) {
         errors.push(Error::OutputDiffers {
             path: path.to_path_buf(),
             actual: normalized,
             output: output.to_vec(),
             expected,
             bless_command: config.config.bless_command.clone(),
+            normalization,
         });
     }
 }
@@ -516,7 +794,22 @@ pub fn bless_output_files(path: &Path, output: &[u8], _errors: &mut Errors, conf
     if output.is_empty() {
         let _ = std::fs::remove_file(path);
     } else {
-        let actual = config.normalize(output, &path.extension().unwrap().to_string_lossy());
+        let kind = config.output_kind(path);
+        let actual = config.normalize(output, &kind);
+        // Keep files that still match, so that e.g. `unordered` outputs don't churn.
+        let actual = match std::fs::read(path) {
+            Ok(expected) if config.comparison(&kind)(&actual, &expected) => return,
+            // Only keep wildcards if the comparison understands them.
+            Ok(expected) => {
+                let preserved = compare::preserve_wildcards(&actual, &expected);
+                if config.comparison(&kind)(&actual, &preserved) {
+                    preserved
+                } else {
+                    actual
+                }
+            }
+            Err(_) => actual,
+        };
         std::fs::write(path, actual).unwrap();
     }
 }
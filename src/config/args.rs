@@ -18,18 +18,38 @@ pub struct Args {
     /// output.
     pub bless: bool,
 
+    /// Whether to rewrite `//~` annotations in the test files to match the
+    /// actual diagnostics.
+    pub bless_annotations: bool,
+
+    /// Report which normalization rules changed the output of failing tests.
+    pub trace_normalization: bool,
+
     /// Only run the test matching the filters exactly.
     pub exact: bool,
 
     /// Whether to only run ignored tests.
     pub ignored: bool,
 
+    /// List the ignored tests and why they were ignored after running the tests.
+    pub show_ignored_reasons: bool,
+
     /// List the tests that can be run.
     pub list: bool,
 
     /// Choose an output format
     pub format: Format,
 
+    /// How to render the diffs of differing output files
+    pub diff: DiffFormat,
+
+    /// How many unchanged lines to show around changes in diffs
+    pub diff_context: Option<usize>,
+
+    /// An external program to show the diffs of differing output files with,
+    /// taken from the `UI_TEST_DIFF_TOOL` environment variable
+    pub diff_tool: Option<String>,
+
     /// The number of threads to use
     pub threads: Option<NonZeroUsize>,
 
@@ -47,11 +67,31 @@ pub enum Format {
     Terse,
 }
 
+/// Possible choices for rendering the diff between expected and actual output.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DiffFormat {
+    /// One line per change, with changes within lines highlighted.
+    #[default]
+    Unified,
+    /// Expected and actual output in two columns next to each other.
+    SideBySide,
+    /// Changed lines are shown as a single line with the removed and added words marked.
+    Word,
+    /// A plain unified diff without colors, for piping to `patch` or other tools.
+    Raw,
+}
+
 impl Args {
     /// Parse the program arguments.
     /// This is meant to be used if `ui_test` is used as a `harness=false` test, called from `cargo test`.
     pub fn test() -> Result<Self> {
-        Self::default().parse_args(std::env::args().skip(1))
+        let args = Self {
+            diff_tool: std::env::var("UI_TEST_DIFF_TOOL")
+                .ok()
+                .filter(|tool| !tool.trim().is_empty()),
+            ..Self::default()
+        };
+        args.parse_args(std::env::args().skip(1))
     }
 
     /// Parse arguments into an existing `Args` struct.
@@ -66,12 +106,18 @@ impl Args {
                 self.check = true;
             } else if arg == "--bless" {
                 self.bless = true;
+            } else if arg == "--bless-annotations" {
+                self.bless_annotations = true;
+            } else if arg == "--trace-normalization" {
+                self.trace_normalization = true;
             } else if arg == "--list" {
                 self.list = true;
             } else if arg == "--exact" {
                 self.exact = true;
             } else if arg == "--ignored" {
                 self.ignored = true;
+            } else if arg == "--show-ignored-reasons" {
+                self.show_ignored_reasons = true;
             } else if arg == "--nocapture" {
                 // We ignore this flag for now.
             } else if let Some(format) = parse_value("--format", &arg, &mut iter)? {
@@ -80,10 +126,20 @@ impl Args {
                     "pretty" => Format::Pretty,
                     _ => bail!("unsupported format `{format}`"),
                 };
+            } else if let Some(n) = parse_value("--diff-context", &arg, &mut iter)? {
+                self.diff_context = Some(n.parse()?);
+            } else if let Some(diff) = parse_value("--diff", &arg, &mut iter)? {
+                self.diff = match &*diff {
+                    "unified" => DiffFormat::Unified,
+                    "side-by-side" => DiffFormat::SideBySide,
+                    "word" => DiffFormat::Word,
+                    "raw" => DiffFormat::Raw,
+                    _ => bail!("unsupported diff format `{diff}`"),
+                };
             } else if let Some(skip) = parse_value("--skip", &arg, &mut iter)? {
                 self.skip.push(skip.into_owned());
             } else if arg == "--help" {
-                bail!("available flags: --quiet, --check, --bless, --test-threads=n, --skip")
+                bail!("available flags: --quiet, --check, --bless, --bless-annotations, --trace-normalization, --show-ignored-reasons, --diff=unified|side-by-side|word|raw, --diff-context=n, --test-threads=n, --skip")
             } else if let Some(n) = parse_value("--test-threads", &arg, &mut iter)? {
                 self.threads = Some(n.parse()?);
             } else if arg.starts_with("--") {
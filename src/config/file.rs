@@ -0,0 +1,195 @@
+//! Loading a [`Config`] from a TOML file, so that test suites can be
+//! configured without touching the test harness.
+
+use super::Config;
+use crate::{display, parser::Comments, Error};
+use color_eyre::eyre::{eyre, Context as _, Result};
+use regex::bytes::Regex;
+use serde::Deserialize;
+use spanned::Spanned;
+use std::{
+    collections::BTreeMap,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+};
+
+/// The contents of a file loaded via [`Config::from_file`].
+/// All paths are relative to the directory of the file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    /// The configuration to start out from.
+    #[serde(default)]
+    base: Base,
+    /// See [`Config::root_dir`].
+    root_dir: PathBuf,
+    /// Replaces the program of the base configuration.
+    program: Option<PathBuf>,
+    /// Appended to the arguments of the base configuration.
+    #[serde(default)]
+    args: Vec<String>,
+    /// Environment variables to set for the program.
+    #[serde(default)]
+    envs: BTreeMap<String, String>,
+    /// See [`Config::out_dir`].
+    out_dir: Option<PathBuf>,
+    /// See [`Config::threads`].
+    threads: Option<NonZeroUsize>,
+    /// See [`Config::bless_command`].
+    bless_command: Option<String>,
+    /// `[regex, replacement]` pairs applied to stderr and stdout.
+    #[serde(default)]
+    filters: Vec<(String, String)>,
+    /// `[regex, replacement]` pairs applied to stderr.
+    #[serde(default)]
+    stderr_filters: Vec<(String, String)>,
+    /// `[regex, replacement]` pairs applied to stdout.
+    #[serde(default)]
+    stdout_filters: Vec<(String, String)>,
+    /// A file with `//@` comments that apply to all tests.
+    comment_defaults: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum Base {
+    /// [`Config::rustc`]
+    #[default]
+    Rustc,
+    /// [`Config::cargo`]
+    Cargo,
+    /// [`Config::dummy`]
+    Dummy,
+}
+
+impl Config {
+    /// Load a configuration from a TOML file like
+    ///
+    /// ```toml
+    /// base = "rustc" # or "cargo" or "dummy"
+    /// root_dir = "tests/ui"
+    /// program = "clippy-driver"
+    /// args = ["-Dwarnings"]
+    /// envs = { RUST_BACKTRACE = "0" }
+    /// threads = 4
+    /// bless_command = "cargo test --test ui -- --bless"
+    /// filters = [["[0-9]+ms", "$$TIME"]]
+    /// stderr_filters = []
+    /// stdout_filters = []
+    /// comment_defaults = "tests/defaults.ui"
+    /// ```
+    ///
+    /// Paths are relative to the directory containing the file.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("failed to read {}", display(path)))?;
+        let file: ConfigFile = toml::from_str(&content)
+            .wrap_err_with(|| format!("failed to parse {}", display(path)))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+
+        let root_dir = dir.join(file.root_dir);
+        let mut config = match file.base {
+            #[cfg(feature = "rustc")]
+            Base::Rustc => Config::rustc(root_dir),
+            #[cfg(feature = "rustc")]
+            Base::Cargo => Config::cargo(root_dir),
+            #[cfg(not(feature = "rustc"))]
+            Base::Rustc | Base::Cargo => {
+                color_eyre::eyre::bail!("`base = \"rustc\"` and `base = \"cargo\"` require the `rustc` feature of `ui_test`")
+            }
+            Base::Dummy => Config {
+                root_dir,
+                ..Config::dummy()
+            },
+        };
+
+        if let Some(program) = file.program {
+            // Allow looking up programs on the `PATH`.
+            config.program.program = if program.components().count() > 1 {
+                dir.join(program)
+            } else {
+                program
+            };
+        }
+        config
+            .program
+            .args
+            .extend(file.args.into_iter().map(Into::into));
+        config.program.envs.extend(
+            file.envs
+                .into_iter()
+                .map(|(k, v)| (k.into(), Some(v.into()))),
+        );
+        if let Some(out_dir) = file.out_dir {
+            config.out_dir = dir.join(out_dir);
+        }
+        config.threads = file.threads.or(config.threads);
+        config.bless_command = file.bless_command.or(config.bless_command);
+
+        if let Some(defaults) = file.comment_defaults {
+            let defaults = dir.join(defaults);
+            let content = Spanned::read_from_file(&defaults)
+                .wrap_err_with(|| format!("failed to read {}", display(&defaults)))?;
+            config.comment_defaults =
+                Comments::parse(content.as_ref(), &config).map_err(|errors| {
+                    let errors: Vec<_> = errors
+                        .iter()
+                        .map(|error| format_comment_error(&content, error))
+                        .collect();
+                    eyre!(
+                        "invalid comments in {}:\n{}",
+                        display(&defaults),
+                        errors.join("\n")
+                    )
+                })?;
+        }
+
+        let regex = |pattern: &str| {
+            Regex::new(pattern).map_err(|err| eyre!("invalid filter in {}: {err}", display(path)))
+        };
+        for (pattern, replacement) in &file.filters {
+            let base = config.comment_defaults.base();
+            base.normalize_stderr
+                .push((regex(pattern)?.into(), replacement.clone().into_bytes()));
+            base.normalize_stdout
+                .push((regex(pattern)?.into(), replacement.clone().into_bytes()));
+        }
+        for (pattern, replacement) in &file.stderr_filters {
+            config
+                .comment_defaults
+                .base()
+                .normalize_stderr
+                .push((regex(pattern)?.into(), replacement.clone().into_bytes()));
+        }
+        for (pattern, replacement) in &file.stdout_filters {
+            config
+                .comment_defaults
+                .base()
+                .normalize_stdout
+                .push((regex(pattern)?.into(), replacement.clone().into_bytes()));
+        }
+
+        Ok(config)
+    }
+}
+
+/// Renders a parse error of the `comment_defaults` file as `path:line:column: message`.
+fn format_comment_error(content: &Spanned<Vec<u8>>, error: &Error) -> String {
+    let Error::InvalidComment { msg, span } = error else {
+        return format!("{error:?}");
+    };
+    let location = content
+        .lines()
+        .enumerate()
+        .find(|(_, line)| line.span.bytes.contains(&span.bytes.start))
+        .map(|(i, line)| {
+            format!(
+                ":{}:{}",
+                i + 1,
+                span.bytes.start - line.span.bytes.start + 1
+            )
+        })
+        .unwrap_or_default();
+    format!("{}{location}: {msg}", display(&span.file))
+}
@@ -11,6 +11,9 @@ use std::{
 #[cfg(feature = "rustc")]
 pub mod edition;
 #[cfg(feature = "rustc")]
+pub mod json_snapshot;
+pub mod needs;
+#[cfg(feature = "rustc")]
 pub mod revision_args;
 #[cfg(feature = "rustc")]
 pub mod run;
@@ -31,9 +34,21 @@ pub trait Flag: Send + Sync + UnwindSafe + RefUnwindSafe + std::fmt::Debug {
         Ok(())
     }
 
-    /// Whether this flag causes a test to be filtered out
-    fn test_condition(&self, _config: &Config, _comments: &Comments, _revision: &str) -> bool {
-        false
+    /// Whether this flag causes a test to be filtered out.
+    /// An error is reported as the result of the test instead.
+    fn test_condition(
+        &self,
+        _config: &Config,
+        _comments: &Comments,
+        _revision: &str,
+    ) -> Result<bool, Errored> {
+        Ok(false)
+    }
+
+    /// Whether this flag enables the given cargo feature for the tests.
+    /// Used by `//@needs-feature`.
+    fn enables_feature(&self, _config: &Config, _feature: &str) -> Result<bool, Errored> {
+        Ok(false)
     }
 
     /// Run an action after a test is finished.
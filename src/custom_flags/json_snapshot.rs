@@ -0,0 +1,52 @@
+//! Custom flag for checking the structured diagnostics of a test against a `.json` file
+
+use super::Flag;
+use crate::{
+    build_manager::BuildManager, diagnostics::rustc::json_snapshot, display,
+    per_test_config::TestConfig, Errored,
+};
+use bstr::ByteSlice;
+use std::process::Output;
+
+/// Compare the level, code, message, primary spans, children and suggestions of all
+/// diagnostics against a `.json` file. This catches span and suggestion changes that
+/// the rendered diagnostics in the `.stderr` file do not show.
+/// Strings in the diagnostics are normalized like stderr, the whole file can be
+/// normalized further with `//@normalize-json`.
+#[derive(Debug, Copy, Clone)]
+pub struct JsonSnapshot;
+
+impl Flag for JsonSnapshot {
+    fn must_be_unique(&self) -> bool {
+        true
+    }
+    fn clone_inner(&self) -> Box<dyn Flag> {
+        Box::new(*self)
+    }
+
+    fn post_test_action(
+        &self,
+        config: &TestConfig,
+        output: &Output,
+        _build_manager: &BuildManager,
+    ) -> Result<(), Errored> {
+        let json = json_snapshot(&output.stderr, |s| {
+            config
+                .normalize(s.as_bytes(), "stderr")
+                .to_str_lossy()
+                .into_owned()
+        });
+        let mut errors = vec![];
+        config.check_output(&json, &mut errors, "json");
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Errored {
+                command: format!("checking {}", display(config.status.path())),
+                errors,
+                stderr: vec![],
+                stdout: vec![],
+            })
+        }
+    }
+}
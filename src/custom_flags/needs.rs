@@ -0,0 +1,91 @@
+//! Custom flags for only running tests if the environment supports them
+
+use super::Flag;
+use crate::{parser::Comments, Config, Errored};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+/// Only run the test if the environment variable is set
+pub struct NeedsEnv(pub String);
+
+impl Flag for NeedsEnv {
+    fn must_be_unique(&self) -> bool {
+        false
+    }
+    fn clone_inner(&self) -> Box<dyn Flag> {
+        Box::new(self.clone())
+    }
+    fn test_condition(
+        &self,
+        _config: &Config,
+        _comments: &Comments,
+        _revision: &str,
+    ) -> Result<bool, Errored> {
+        Ok(std::env::var_os(&self.0).is_none())
+    }
+}
+
+#[derive(Debug, Clone)]
+/// Only run the test if the program can be found on the `PATH`
+pub struct NeedsProgram(pub String);
+
+impl Flag for NeedsProgram {
+    fn must_be_unique(&self) -> bool {
+        false
+    }
+    fn clone_inner(&self) -> Box<dyn Flag> {
+        Box::new(self.clone())
+    }
+    fn test_condition(
+        &self,
+        _config: &Config,
+        _comments: &Comments,
+        _revision: &str,
+    ) -> Result<bool, Errored> {
+        Ok(!program_exists(&self.0))
+    }
+}
+
+fn program_exists(name: &str) -> bool {
+    let exists = |path: &Path| {
+        path.is_file()
+            || (!std::env::consts::EXE_SUFFIX.is_empty()
+                && path
+                    .with_extension(&std::env::consts::EXE_SUFFIX[1..])
+                    .is_file())
+    };
+    if Path::new(name).components().count() > 1 {
+        return exists(Path::new(name));
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|paths| std::env::split_paths(&paths).any(|dir| exists(&dir.join(name))))
+}
+
+#[derive(Debug, Clone)]
+/// Only run the test if the cargo feature is enabled for the crate
+/// whose dependencies are made available to the tests.
+pub struct NeedsFeature(pub String);
+
+impl Flag for NeedsFeature {
+    fn must_be_unique(&self) -> bool {
+        false
+    }
+    fn clone_inner(&self) -> Box<dyn Flag> {
+        Box::new(self.clone())
+    }
+    fn test_condition(
+        &self,
+        config: &Config,
+        comments: &Comments,
+        revision: &str,
+    ) -> Result<bool, Errored> {
+        for revisioned in comments.for_revision(revision) {
+            for flag in revisioned.custom.values().flat_map(|flags| &flags.content) {
+                if flag.enables_feature(config, &self.0)? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+}
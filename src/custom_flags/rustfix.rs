@@ -11,7 +11,7 @@ use crate::{
 use rustfix::{CodeFix, Filter, Suggestion};
 use spanned::{Span, Spanned};
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     path::{Path, PathBuf},
     process::Output,
     sync::Arc,
@@ -186,12 +186,18 @@ fn compile_fixed(
             Revisioned {
                 span: Span::default(),
                 ignore: vec![],
+                ignore_spans: vec![],
                 only: vec![],
+                only_spans: vec![],
                 stderr_per_bitwidth: false,
+                shared_snapshot: false,
                 compile_flags: config.collect(|r| r.compile_flags.iter().cloned()),
                 env_vars: config.collect(|r| r.env_vars.iter().cloned()),
                 normalize_stderr: vec![],
                 normalize_stdout: vec![],
+                normalize_outputs: BTreeMap::new(),
+                normalization_spans: vec![],
+                compare: BTreeMap::new(),
                 error_in_other_files: vec![],
                 error_matches: vec![],
                 require_annotations_for_level: Default::default(),
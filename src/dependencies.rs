@@ -11,11 +11,12 @@ use bstr::ByteSlice;
 use cargo_metadata::{camino::Utf8PathBuf, BuildScript, DependencyKind};
 use cargo_platform::Cfg;
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BTreeSet, HashMap, HashSet},
     ffi::OsString,
     path::PathBuf,
     process::Command,
     str::FromStr,
+    sync::{Arc, OnceLock},
 };
 
 #[derive(Default, Debug)]
@@ -30,7 +31,7 @@ pub struct Dependencies {
     pub dependencies: Vec<(String, Vec<Utf8PathBuf>)>,
 }
 
-fn cfgs(config: &Config) -> Result<Vec<Cfg>, Errored> {
+pub(crate) fn cfgs(config: &Config) -> Result<Vec<Cfg>, Errored> {
     let Some(cfg) = &config.program.cfg_flag else {
         return Ok(vec![]);
     };
@@ -87,13 +88,6 @@ fn build_dependencies_inner(
         }
     }
 
-    // Reusable closure for setting up the environment both for artifact generation and `cargo_metadata`
-    let set_locking = |cmd: &mut Command| {
-        if !info.bless_lockfile {
-            cmd.arg("--locked");
-        }
-    };
-
     build.arg("--message-format=json");
 
     let output = config.run_command(&mut build)?;
@@ -186,12 +180,111 @@ fn build_dependencies_inner(
     }
 
     // Check which crates are mentioned in the crate itself
+    let metadata = cargo_metadata(config, info)?;
+    let cfg = cfgs(config)?;
+
+    // Only take artifacts that are defined in the Cargo.toml
+
+    // First, find the root artifact
+    let root = root_package(&metadata, info)?;
+
+    // Then go over all of its dependencies
+    let mut dependencies = root
+        .dependencies
+        .iter()
+        .filter(|dep| matches!(dep.kind, DependencyKind::Normal))
+        // Only consider dependencies that are enabled on the current target
+        .filter(|dep| match &dep.target {
+            Some(platform) => platform.matches(config.target.as_ref().unwrap(), &cfg),
+            None => true,
+        })
+        .map(|dep| {
+            for p in &metadata.packages {
+                if p.name != dep.name {
+                    continue;
+                }
+                if dep
+                    .path
+                    .as_ref()
+                    .is_some_and(|path| p.manifest_path.parent().unwrap() == path)
+                    || dep.req.matches(&p.version)
+                {
+                    return (p, dep.rename.clone().unwrap_or_else(|| p.name.clone()));
+                }
+            }
+            panic!("dep not found: {dep:#?}")
+        })
+        // Also expose the root crate
+        .chain(std::iter::once((root, root.name.clone())))
+        .filter_map(|(package, name)| {
+            // Get the id for the package matching the version requirement of the dep
+            let id = &package.id;
+            // Return the name chosen in `Cargo.toml` and the path to the corresponding artifact
+            match artifacts.remove(id) {
+                Some(Ok((_, artifacts))) => Some(Ok((name.replace('-', "_"), artifacts))),
+                Some(Err(what)) => Some(Err(Errored {
+                    command: what,
+                    errors: vec![],
+                    stderr: id.to_string().into_bytes(),
+                    stdout: "`ui_test` does not support crates that appear as both build-dependencies and core dependencies".as_bytes().into(),
+                })),
+                None => {
+                    if name == root.name {
+                        // If there are no artifacts, this is the root crate and it is being built as a binary/test
+                        // instead of a library. We simply add no artifacts, meaning you can't depend on functions
+                        // and types declared in the root crate.
+                        None
+                    } else {
+                        panic!("no artifact found for `{name}`(`{id}`):`\n{}", artifact_output.to_str().unwrap())
+                    }
+                }
+            }
+        })
+        .collect::<Result<Vec<_>, Errored>>()?;
+    let import_paths = import_paths.into_iter().collect();
+    let import_libs = import_libs.into_iter().collect();
+
+    if info.build_std.is_some() {
+        let mut build_std_crates = HashSet::new();
+        build_std_crates.insert("core");
+        build_std_crates.insert("alloc");
+        build_std_crates.insert("proc_macro");
+        build_std_crates.insert("panic_unwind");
+        build_std_crates.insert("compiler_builtins");
+        build_std_crates.insert("std");
+        build_std_crates.insert("test");
+        build_std_crates.insert("panic_abort");
+
+        for (name, artifacts) in artifacts
+            .into_iter()
+            .filter_map(|(_, artifacts)| artifacts.ok())
+        {
+            if build_std_crates.remove(name.as_str()) {
+                dependencies.push((format!("noprelude:{name}"), artifacts));
+            }
+        }
+    }
+
+    Ok(Dependencies {
+        dependencies,
+        import_paths,
+        import_libs,
+    })
+}
+
+/// Runs `cargo metadata` for the crate at `crate_manifest_path`.
+fn cargo_metadata(
+    config: &Config,
+    info: &DependencyBuilder,
+) -> Result<cargo_metadata::Metadata, Errored> {
     let mut metadata = cargo_metadata::MetadataCommand::new().cargo_command();
     metadata
         .arg("--manifest-path")
         .arg(&info.crate_manifest_path);
     info.program.apply_env(&mut metadata);
-    set_locking(&mut metadata);
+    if !info.bless_lockfile {
+        metadata.arg("--locked");
+    }
     let output = config.run_command(&mut metadata)?;
 
     if !output.status.success() {
@@ -203,125 +296,53 @@ fn build_dependencies_inner(
         });
     }
 
-    let output = output.stdout;
-
-    let cfg = cfgs(config)?;
-
-    for line in output.lines() {
-        if !line.starts_with(b"{") {
-            continue;
-        }
-        let metadata: cargo_metadata::Metadata =
-            serde_json::from_slice(line).map_err(|err| Errored {
-                command: "decoding cargo metadata json".into(),
-                errors: vec![],
-                stderr: err.to_string().into_bytes(),
-                stdout: vec![],
-            })?;
-        // Only take artifacts that are defined in the Cargo.toml
-
-        // First, find the root artifact
-        let root = metadata
-            .packages
-            .iter()
-            .find(|package| {
-                package.manifest_path.as_std_path().canonicalize().unwrap()
-                    == info.crate_manifest_path.canonicalize().unwrap()
-            })
-            .unwrap();
-
-        // Then go over all of its dependencies
-        let mut dependencies = root
-            .dependencies
-            .iter()
-            .filter(|dep| matches!(dep.kind, DependencyKind::Normal))
-            // Only consider dependencies that are enabled on the current target
-            .filter(|dep| match &dep.target {
-                Some(platform) => platform.matches(config.target.as_ref().unwrap(), &cfg),
-                None => true,
-            })
-            .map(|dep| {
-                for p in &metadata.packages {
-                    if p.name != dep.name {
-                        continue;
-                    }
-                    if dep
-                        .path
-                        .as_ref()
-                        .is_some_and(|path| p.manifest_path.parent().unwrap() == path)
-                        || dep.req.matches(&p.version)
-                    {
-                        return (p, dep.rename.clone().unwrap_or_else(|| p.name.clone()));
-                    }
-                }
-                panic!("dep not found: {dep:#?}")
-            })
-            // Also expose the root crate
-            .chain(std::iter::once((root, root.name.clone())))
-            .filter_map(|(package, name)| {
-                // Get the id for the package matching the version requirement of the dep
-                let id = &package.id;
-                // Return the name chosen in `Cargo.toml` and the path to the corresponding artifact
-                match artifacts.remove(id) {
-                    Some(Ok((_, artifacts))) => Some(Ok((name.replace('-', "_"), artifacts))),
-                    Some(Err(what)) => Some(Err(Errored {
-                        command: what,
-                        errors: vec![],
-                        stderr: id.to_string().into_bytes(),
-                        stdout: "`ui_test` does not support crates that appear as both build-dependencies and core dependencies".as_bytes().into(),
-                    })),
-                    None => {
-                        if name == root.name {
-                            // If there are no artifacts, this is the root crate and it is being built as a binary/test
-                            // instead of a library. We simply add no artifacts, meaning you can't depend on functions
-                            // and types declared in the root crate.
-                            None
-                        } else {
-                            panic!("no artifact found for `{name}`(`{id}`):`\n{}", artifact_output.to_str().unwrap())
-                        }
-                    }
-                }
-            })
-            .collect::<Result<Vec<_>, Errored>>()?;
-        let import_paths = import_paths.into_iter().collect();
-        let import_libs = import_libs.into_iter().collect();
-
-        if info.build_std.is_some() {
-            let mut build_std_crates = HashSet::new();
-            build_std_crates.insert("core");
-            build_std_crates.insert("alloc");
-            build_std_crates.insert("proc_macro");
-            build_std_crates.insert("panic_unwind");
-            build_std_crates.insert("compiler_builtins");
-            build_std_crates.insert("std");
-            build_std_crates.insert("test");
-            build_std_crates.insert("panic_abort");
-
-            for (name, artifacts) in artifacts
-                .into_iter()
-                .filter_map(|(_, artifacts)| artifacts.ok())
-            {
-                if build_std_crates.remove(name.as_str()) {
-                    dependencies.push((format!("noprelude:{name}"), artifacts));
-                }
-            }
-        }
-
-        return Ok(Dependencies {
-            dependencies,
-            import_paths,
-            import_libs,
+    let Some(line) = output.stdout.lines().find(|line| line.starts_with(b"{")) else {
+        return Err(Errored {
+            command: "looking for json in cargo-metadata output".into(),
+            errors: vec![],
+            stderr: vec![],
+            stdout: output.stdout,
         });
-    }
-
-    Err(Errored {
-        command: "looking for json in cargo-metadata output".into(),
+    };
+    serde_json::from_slice(line).map_err(|err| Errored {
+        command: "decoding cargo metadata json".into(),
         errors: vec![],
-        stderr: vec![],
+        stderr: err.to_string().into_bytes(),
         stdout: vec![],
     })
 }
 
+/// Finds the package of the crate at `crate_manifest_path` in its `cargo metadata`.
+fn root_package<'a>(
+    metadata: &'a cargo_metadata::Metadata,
+    info: &DependencyBuilder,
+) -> Result<&'a cargo_metadata::Package, Errored> {
+    let not_found = |stderr: String| Errored {
+        command: format!(
+            "looking for {} in cargo-metadata output",
+            info.crate_manifest_path.display()
+        ),
+        errors: vec![],
+        stderr: stderr.into_bytes(),
+        stdout: vec![],
+    };
+    let manifest_path = info
+        .crate_manifest_path
+        .canonicalize()
+        .map_err(|err| not_found(err.to_string()))?;
+    metadata
+        .packages
+        .iter()
+        .find(|package| {
+            package
+                .manifest_path
+                .as_std_path()
+                .canonicalize()
+                .is_ok_and(|path| path == manifest_path)
+        })
+        .ok_or_else(|| not_found("no package with this manifest".into()))
+}
+
 /// Build the dependencies.
 #[derive(Debug, Clone)]
 pub struct DependencyBuilder {
@@ -335,8 +356,15 @@ pub struct DependencyBuilder {
     pub build_std: Option<String>,
     /// Whether the lockfile can be overwritten
     pub bless_lockfile: bool,
+    /// The result of [`DependencyBuilder::enabled_features`], computed on first use.
+    /// Clones of the builder share it, so configure the builder before cloning it.
+    pub feature_cache: FeatureCache,
 }
 
+/// Caches the features enabled for the tests, see [`DependencyBuilder::enabled_features`].
+#[derive(Debug, Clone, Default)]
+pub struct FeatureCache(Arc<OnceLock<Result<BTreeSet<String>, ()>>>);
+
 impl Default for DependencyBuilder {
     fn default() -> Self {
         Self {
@@ -344,6 +372,7 @@ impl Default for DependencyBuilder {
             program: CommandBuilder::cargo(),
             build_std: None,
             bless_lockfile: false,
+            feature_cache: FeatureCache::default(),
         }
     }
 }
@@ -365,6 +394,91 @@ impl Flag for DependencyBuilder {
         cmd.args(extra_args);
         Ok(())
     }
+    fn enables_feature(&self, config: &Config, feature: &str) -> Result<bool, Errored> {
+        Ok(self.enabled_features(config)?.contains(feature))
+    }
+}
+
+impl DependencyBuilder {
+    /// The features enabled for the crate at `crate_manifest_path` by the
+    /// `--features`, `--all-features` and `--no-default-features` arguments of `program`,
+    /// including the features they enable transitively.
+    /// Runs `cargo metadata` on the first call only, the result is kept in `feature_cache`.
+    /// The error is only reported once, later calls get a generic error.
+    pub fn enabled_features(&self, config: &Config) -> Result<&BTreeSet<String>, Errored> {
+        let mut err = None;
+        self.feature_cache
+            .0
+            .get_or_init(|| {
+                self.compute_enabled_features(config)
+                    .map_err(|e| err = Some(e))
+            })
+            .as_ref()
+            .map_err(|()| {
+                err.unwrap_or_else(|| Errored {
+                    command: "cargo metadata".into(),
+                    errors: vec![],
+                    stderr: b"previous `cargo metadata` invocation failed".to_vec(),
+                    stdout: vec![],
+                })
+            })
+    }
+
+    fn compute_enabled_features(&self, config: &Config) -> Result<BTreeSet<String>, Errored> {
+        let mut all_features = false;
+        let mut default_features = true;
+        let mut requested = vec![];
+        let mut args = self.program.args.iter().map(|arg| arg.to_string_lossy());
+        while let Some(arg) = args.next() {
+            let list = match &*arg {
+                "--all-features" => {
+                    all_features = true;
+                    continue;
+                }
+                "--no-default-features" => {
+                    default_features = false;
+                    continue;
+                }
+                "--features" | "-F" => args.next().unwrap_or_default(),
+                _ => match arg.strip_prefix("--features=") {
+                    Some(list) => list.to_owned().into(),
+                    None => continue,
+                },
+            };
+            requested.extend(
+                list.split([',', ' '])
+                    .filter(|feature| !feature.is_empty())
+                    .map(String::from),
+            );
+        }
+
+        let metadata = cargo_metadata(config, self)?;
+        let root = root_package(&metadata, self)?;
+
+        if all_features {
+            requested.extend(root.features.keys().cloned());
+        }
+        if default_features && root.features.contains_key("default") {
+            requested.push("default".into());
+        }
+
+        let mut enabled = BTreeSet::new();
+        while let Some(feature) = requested.pop() {
+            if let Some(implied) = root.features.get(&feature) {
+                // `dep:name` and `name/feature` refer to dependencies, not features of this crate
+                requested.extend(
+                    implied
+                        .iter()
+                        .filter(|implied| {
+                            !implied.contains([':', '/']) && !enabled.contains(*implied)
+                        })
+                        .cloned(),
+                );
+            }
+            enabled.insert(feature);
+        }
+        Ok(enabled)
+    }
 }
 
 impl Build for DependencyBuilder {
@@ -42,6 +42,20 @@ impl std::str::FromStr for Level {
     }
 }
 
+impl Level {
+    /// How this level is written in `//~` annotations.
+    pub(crate) fn annotation_name(self) -> &'static str {
+        match self {
+            Self::Ice => "ICE",
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Help => "HELP",
+            Self::Note => "NOTE",
+            Self::FailureNote => "failure-note",
+        }
+    }
+}
+
 /// A diagnostic message.
 #[derive(Debug)]
 pub struct Message {
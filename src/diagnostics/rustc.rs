@@ -5,8 +5,9 @@
 
 use super::{Diagnostics, Level, Message};
 use bstr::ByteSlice;
-use cargo_metadata::diagnostic::{Diagnostic, DiagnosticLevel, DiagnosticSpan};
+use cargo_metadata::diagnostic::{Applicability, Diagnostic, DiagnosticLevel, DiagnosticSpan};
 use regex::Regex;
+use serde::Serialize;
 use std::{
     path::{Path, PathBuf},
     sync::OnceLock,
@@ -176,3 +177,88 @@ impl From<DiagnosticLevel> for Level {
         }
     }
 }
+
+/// The stable subset of a [`Diagnostic`] that is stored in `.json` snapshots.
+#[derive(Serialize)]
+struct SnapshotDiagnostic {
+    level: DiagnosticLevel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<String>,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    spans: Vec<SnapshotSpan>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    children: Vec<SnapshotDiagnostic>,
+}
+
+/// The primary spans of a diagnostic, without the byte offsets and source snippets
+/// that change with unrelated edits to the test.
+#[derive(Serialize)]
+struct SnapshotSpan {
+    file: String,
+    line_start: usize,
+    column_start: usize,
+    line_end: usize,
+    column_end: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    label: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggested_replacement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion_applicability: Option<Applicability>,
+}
+
+impl SnapshotDiagnostic {
+    fn new(diag: Diagnostic, normalize: &impl Fn(&str) -> String) -> Self {
+        Self {
+            level: diag.level,
+            code: diag.code.map(|code| code.code),
+            message: normalize(&diag.message),
+            spans: diag
+                .spans
+                .into_iter()
+                .filter(|span| span.is_primary)
+                .map(|span| SnapshotSpan {
+                    file: normalize(&span.file_name),
+                    line_start: span.line_start,
+                    column_start: span.column_start,
+                    line_end: span.line_end,
+                    column_end: span.column_end,
+                    label: span.label.as_deref().map(normalize),
+                    suggested_replacement: span.suggested_replacement.as_deref().map(normalize),
+                    suggestion_applicability: span.suggestion_applicability,
+                })
+                .collect(),
+            children: diag
+                .children
+                .into_iter()
+                .map(|child| Self::new(child, normalize))
+                .collect(),
+        }
+    }
+}
+
+/// Extracts the stable subset of the diagnostics (level, code, message, primary spans,
+/// children and suggestions) from `rustc` or `cargo` JSON output as pretty-printed JSON.
+/// `normalize` is applied to all strings. Returns an empty output if there are no diagnostics.
+pub fn json_snapshot(stderr: &[u8], normalize: impl Fn(&str) -> String) -> Vec<u8> {
+    let diagnostics: Vec<_> = stderr
+        .lines()
+        .filter(|line| line.starts_with_str(b"{"))
+        .filter_map(|line| {
+            serde_json::from_slice::<Diagnostic>(line).ok().or_else(|| {
+                match serde_json::from_slice::<cargo_metadata::Message>(line).ok()? {
+                    cargo_metadata::Message::CompilerMessage(msg) => Some(msg.message),
+                    _ => None,
+                }
+            })
+        })
+        .map(|diag| SnapshotDiagnostic::new(diag, &normalize))
+        .collect();
+    if diagnostics.is_empty() {
+        return vec![];
+    }
+    let mut json = serde_json::to_vec_pretty(&diagnostics).unwrap();
+    json.push(b'\n');
+    json
+}
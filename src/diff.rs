@@ -1,34 +1,93 @@
+use crate::DiffFormat;
 use colored::*;
 use prettydiff::{basic::DiffOp, basic::DiffOp::*, diff_lines, diff_words};
+use std::fmt::Write as _;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-/// How many lines of context are displayed around the actual diffs
-const CONTEXT: usize = 2;
+#[cfg(test)]
+mod tests;
 
-fn skip(skipped_lines: &[&str]) {
-    // When the amount of skipped lines is exactly `CONTEXT * 2`, we already
+/// How diffs are rendered.
+#[derive(Clone, Debug)]
+pub(crate) struct DiffOptions {
+    pub(crate) format: DiffFormat,
+    /// How many lines of context are displayed around the actual diffs
+    pub(crate) context: usize,
+    /// An external program (with arguments) that gets invoked with the paths of the
+    /// expected and actual output instead of printing a diff.
+    pub(crate) tool: Option<String>,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            format: DiffFormat::Unified,
+            context: 2,
+            tool: None,
+        }
+    }
+}
+
+/// Writes `actual` to a temporary file with the same file name as `expected` (so that tools can
+/// detect the file type), and runs `tool` on both files, like `git difftool` does.
+/// Diff tools usually exit with a failure status if the files differ, so only failing to
+/// run the tool at all is an error.
+pub(crate) fn run_diff_tool(
+    tool: &str,
+    expected: &Path,
+    actual: &[u8],
+) -> std::io::Result<ExitStatus> {
+    let mut args = tool.split_whitespace();
+    let program = args.next().ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "no program specified")
+    })?;
+    // Every output gets a directory of its own, as tests fail in parallel.
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "ui_test_diff_{}_{}",
+        std::process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir)?;
+    let actual_path = dir.join(expected.file_name().unwrap_or("actual".as_ref()));
+    let status = std::fs::write(&actual_path, actual).and_then(|()| {
+        Command::new(program)
+            .args(args)
+            .arg(expected)
+            .arg(&actual_path)
+            .status()
+    });
+    let _ = std::fs::remove_dir_all(&dir);
+    status
+}
+
+fn skip(skipped_lines: &[&str], context: usize, print_line: impl Fn(&str)) {
+    // When the amount of skipped lines is exactly `context * 2`, we already
     // print all the context and don't actually skip anything.
-    match skipped_lines.len().checked_sub(CONTEXT * 2) {
+    match skipped_lines.len().checked_sub(context * 2) {
         Some(skipped @ 2..) => {
-            // Print an initial `CONTEXT` amount of lines.
-            for line in &skipped_lines[..CONTEXT] {
-                println!(" {line}");
+            // Print an initial `context` amount of lines.
+            for line in &skipped_lines[..context] {
+                print_line(line);
             }
             println!("... {skipped} lines skipped ...");
-            // Print `... n lines skipped ...` followed by the last `CONTEXT` lines.
-            for line in &skipped_lines[skipped + CONTEXT..] {
-                println!(" {line}");
+            // Print `... n lines skipped ...` followed by the last `context` lines.
+            for line in &skipped_lines[skipped + context..] {
+                print_line(line);
             }
         }
         _ => {
             // Print all the skipped lines if the amount of context desired is less than the amount of lines
             for line in skipped_lines {
-                println!(" {line}");
+                print_line(line);
             }
         }
     }
 }
 
-fn row(row: DiffOp<'_, &str>) {
+fn row(row: DiffOp<'_, &str>, options: &DiffOptions) {
     match row {
         Remove(l) => {
             for l in l {
@@ -36,10 +95,12 @@ fn row(row: DiffOp<'_, &str>) {
             }
         }
         Equal(l) => {
-            skip(l);
+            skip(l, options.context, |line| println!(" {line}"));
         }
         Replace(l, r) => {
-            if l.len() == r.len() {
+            if options.format == DiffFormat::Word {
+                print_word_diff(&l.join("\n"), &r.join("\n"));
+            } else if l.len() == r.len() {
                 for (l, r) in l.iter().zip(r) {
                     print_line_diff(l, r);
                 }
@@ -136,6 +197,131 @@ fn print_line_diff(l: &str, r: &str) {
     }
 }
 
+/// Prints the changes as a single text with the removed and added words marked,
+/// like `git diff --word-diff`.
+fn print_word_diff(l: &str, r: &str) {
+    let colorize = colored::control::SHOULD_COLORIZE.should_colorize();
+    // Keep the `~` prefix on every line of multi-line changes.
+    let text = |words: &[&str]| words.concat().replace('\n', "\n~");
+    print!("{}", "~".yellow());
+    for word in diff_words(l, r).diff() {
+        let (removed, added) = match word {
+            Equal(w) => {
+                print!("{}", text(w));
+                continue;
+            }
+            Remove(l) => (l, &[][..]),
+            Insert(r) => (&[][..], r),
+            Replace(l, r) => (l, r),
+        };
+        if !removed.is_empty() {
+            match colorize {
+                true => print!("{}", text(removed).on_red()),
+                false => print!("[-{}-]", text(removed)),
+            }
+        }
+        if !added.is_empty() {
+            match colorize {
+                true => print!("{}", text(added).on_green()),
+                false => print!("{{+{}+}}", text(added)),
+            }
+        }
+    }
+    println!();
+}
+
+/// Prints the expected and actual lines in two columns next to each other, separated by
+/// `|` for changed, `<` for removed and `>` for added lines, like `sdiff`.
+fn print_side_by_side(ops: Vec<DiffOp<'_, &str>>, width: usize, context: usize) {
+    let line = |l: &str, marker: char, r: &str| {
+        let l = format!("{l:<width$}");
+        match marker {
+            '<' => println!("{} {}", l.red(), marker.to_string().red()),
+            '>' => println!("{l} {} {}", marker.to_string().green(), r.green()),
+            '|' => println!("{} {} {}", l.red(), marker.to_string().yellow(), r.green()),
+            _ => println!("{l}   {r}"),
+        }
+    };
+    for op in ops {
+        match op {
+            Equal(l) => skip(l, context, |l| line(l, ' ', l)),
+            Remove(l) => l.iter().for_each(|l| line(l, '<', "")),
+            Insert(r) => r.iter().for_each(|r| line("", '>', r)),
+            Replace(l, r) => {
+                for i in 0..l.len().max(r.len()) {
+                    match (l.get(i), r.get(i)) {
+                        (Some(l), Some(r)) => line(l, '|', r),
+                        (Some(l), None) => line(l, '<', ""),
+                        (None, Some(r)) => line("", '>', r),
+                        (None, None) => unreachable!(),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Renders a plain unified diff with `@@` hunk headers, like `diff -u` without the file
+/// name header. Unlike the other formats, this keeps track of missing newlines at the end of
+/// either side, so that the diff can be applied with `patch` or `git apply`.
+fn raw_diff(expected: &str, actual: &str, context: usize) -> String {
+    let expected: Vec<_> = expected.split_inclusive('\n').collect();
+    let actual: Vec<_> = actual.split_inclusive('\n').collect();
+    let mut lines = vec![];
+    for op in prettydiff::basic::diff(&expected, &actual) {
+        match op {
+            Equal(l) => lines.extend(l.iter().map(|l| (' ', *l))),
+            Remove(l) => lines.extend(l.iter().map(|l| ('-', *l))),
+            Insert(r) => lines.extend(r.iter().map(|r| ('+', *r))),
+            Replace(l, r) => {
+                lines.extend(l.iter().map(|l| ('-', *l)));
+                lines.extend(r.iter().map(|r| ('+', *r)));
+            }
+        }
+    }
+    let count =
+        |lines: &[(char, &str)], skip: char| lines.iter().filter(|(tag, _)| *tag != skip).count();
+    let mut out = String::new();
+    let mut hunk = |start: usize, end: usize| {
+        let (old_len, new_len) = (
+            count(&lines[start..end], '+'),
+            count(&lines[start..end], '-'),
+        );
+        // Empty ranges start at the line before them.
+        let old_start = count(&lines[..start], '+') + usize::from(old_len > 0);
+        let new_start = count(&lines[..start], '-') + usize::from(new_len > 0);
+        writeln!(out, "@@ -{old_start},{old_len} +{new_start},{new_len} @@").unwrap();
+        for (tag, line) in &lines[start..end] {
+            match line.strip_suffix('\n') {
+                Some(line) => writeln!(out, "{tag}{line}").unwrap(),
+                None => writeln!(out, "{tag}{line}\n\\ No newline at end of file").unwrap(),
+            }
+        }
+    };
+    let mut changes = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, (tag, _))| *tag != ' ')
+        .map(|(i, _)| {
+            (
+                i.saturating_sub(context),
+                (i + 1 + context).min(lines.len()),
+            )
+        });
+    let Some((mut start, mut end)) = changes.next() else {
+        return out;
+    };
+    for (next_start, next_end) in changes {
+        if next_start > end {
+            hunk(start, end);
+            start = next_start;
+        }
+        end = next_end;
+    }
+    hunk(start, end);
+    out
+}
+
 fn has_both_insertions_and_deletions(diff: &[DiffOp<'_, &str>]) -> bool {
     let mut seen_l = false;
     let mut seen_r = false;
@@ -151,7 +337,7 @@ fn has_both_insertions_and_deletions(diff: &[DiffOp<'_, &str>]) -> bool {
     seen_l && seen_r
 }
 
-pub(crate) fn print_diff(expected: &[u8], actual: &[u8]) {
+pub(crate) fn print_diff(expected: &[u8], actual: &[u8], options: &DiffOptions) {
     let expected_str = String::from_utf8_lossy(expected);
     let actual_str = String::from_utf8_lossy(actual);
 
@@ -162,12 +348,26 @@ pub(crate) fn print_diff(expected: &[u8], actual: &[u8]) {
         );
     }
 
-    let pat = |c: char| c.is_whitespace() && c != ' ' && c != '\n' && c != '\r';
-    let expected_str = expected_str.replace(pat, "░");
-    let actual_str = actual_str.replace(pat, "░");
+    // Make invisible whitespace visible, unless the diff is meant for other tools.
+    let (expected_str, actual_str) = if options.format == DiffFormat::Raw {
+        (expected_str.into_owned(), actual_str.into_owned())
+    } else {
+        let pat = |c: char| c.is_whitespace() && c != ' ' && c != '\n' && c != '\r';
+        (expected_str.replace(pat, "░"), actual_str.replace(pat, "░"))
+    };
 
-    for r in diff_lines(&expected_str, &actual_str).diff() {
-        row(r);
+    let diff = diff_lines(&expected_str, &actual_str);
+    match options.format {
+        DiffFormat::Unified | DiffFormat::Word => {
+            for r in diff.diff() {
+                row(r, options);
+            }
+        }
+        DiffFormat::SideBySide => {
+            let width = expected_str.lines().map(|l| l.chars().count()).max();
+            print_side_by_side(diff.diff(), width.unwrap_or_default(), options.context);
+        }
+        DiffFormat::Raw => print!("{}", raw_diff(&expected_str, &actual_str, options.context)),
     }
     println!()
 }
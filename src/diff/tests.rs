@@ -0,0 +1,104 @@
+use super::{raw_diff, run_diff_tool};
+
+#[test]
+fn raw_diff_equal() {
+    assert_eq!(raw_diff("a\nb\n", "a\nb\n", 2), "");
+    assert_eq!(raw_diff("", "", 2), "");
+}
+
+#[test]
+fn raw_diff_single_change() {
+    let expected = "1\n2\n3\n4\n5\n6\n7\n";
+    let actual = "1\n2\n3\nfour\n5\n6\n7\n";
+    assert_eq!(
+        raw_diff(expected, actual, 2),
+        "@@ -2,5 +2,5 @@\n 2\n 3\n-4\n+four\n 5\n 6\n"
+    );
+    assert_eq!(
+        raw_diff(expected, actual, 0),
+        "@@ -4,1 +4,1 @@\n-4\n+four\n"
+    );
+}
+
+#[test]
+fn raw_diff_hunks() {
+    let expected = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+    // Changes further apart than twice the context get their own hunk.
+    let actual = "one\n2\n3\n4\n5\n6\n7\n8\nnine\n";
+    assert_eq!(
+        raw_diff(expected, actual, 1),
+        "@@ -1,2 +1,2 @@\n-1\n+one\n 2\n@@ -8,2 +8,2 @@\n 8\n-9\n+nine\n"
+    );
+    // Otherwise their contexts are merged into one hunk.
+    let actual = "1\ntwo\n3\n4\nfive\n6\n7\n8\n9\n";
+    assert_eq!(
+        raw_diff(expected, actual, 1),
+        "@@ -1,6 +1,6 @@\n 1\n-2\n+two\n 3\n 4\n-5\n+five\n 6\n"
+    );
+}
+
+#[test]
+fn raw_diff_insertion_and_removal() {
+    assert_eq!(
+        raw_diff("1\n2\n", "1\nnew\n2\n", 0),
+        "@@ -1,0 +2,1 @@\n+new\n"
+    );
+    assert_eq!(
+        raw_diff("1\nold\n2\n", "1\n2\n", 0),
+        "@@ -2,1 +1,0 @@\n-old\n"
+    );
+}
+
+#[test]
+fn raw_diff_empty_sides() {
+    assert_eq!(raw_diff("", "a\nb\n", 2), "@@ -0,0 +1,2 @@\n+a\n+b\n");
+    assert_eq!(raw_diff("a\nb\n", "", 2), "@@ -1,2 +0,0 @@\n-a\n-b\n");
+}
+
+#[test]
+fn raw_diff_trailing_newline() {
+    assert_eq!(
+        raw_diff("a\nb\n", "a\nb", 2),
+        "@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+    );
+    assert_eq!(
+        raw_diff("a\nb", "a\nb\n", 2),
+        "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+b\n"
+    );
+    assert_eq!(
+        raw_diff("a", "b", 2),
+        "@@ -1,1 +1,1 @@\n-a\n\\ No newline at end of file\n+b\n\\ No newline at end of file\n"
+    );
+}
+
+#[test]
+// The stub tools are only available on unix.
+#[cfg(unix)]
+fn diff_tool() {
+    // Unit tests run in the crate root.
+    let expected = std::path::Path::new("Cargo.toml");
+    let contents = std::fs::read(expected).unwrap();
+    assert!(run_diff_tool("true", expected, b"").unwrap().success());
+    // A failure status just means that the files differ.
+    assert!(!run_diff_tool("false", expected, b"").unwrap().success());
+    // The actual output is passed as a second file, after any arguments.
+    assert!(run_diff_tool("cmp -s", expected, &contents)
+        .unwrap()
+        .success());
+    assert!(!run_diff_tool("cmp -s", expected, b"different")
+        .unwrap()
+        .success());
+    // Failing to run the tool is an error.
+    assert!(run_diff_tool("", expected, b"").is_err());
+    assert!(run_diff_tool("ui_test_no_such_diff_tool", expected, b"").is_err());
+    // The temporary files are removed afterwards.
+    let prefix = format!("ui_test_diff_{}_", std::process::id());
+    let leftovers: Vec<_> = std::fs::read_dir(std::env::temp_dir())
+        .unwrap()
+        .filter(|entry| {
+            let name = entry.as_ref().unwrap().file_name();
+            name.to_string_lossy().starts_with(&prefix)
+        })
+        .collect();
+    assert!(leftovers.is_empty(), "{leftovers:?}");
+}
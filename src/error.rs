@@ -1,5 +1,6 @@
 use crate::{
-    diagnostics::Message,
+    diagnostics::{Level, Message},
+    filter::RuleApplication,
     parser::{Pattern, Span, Spanned},
 };
 use std::{num::NonZeroUsize, path::PathBuf, process::ExitStatus};
@@ -21,15 +22,30 @@ pub enum Error {
     PatternNotFound {
         /// The pattern that was not found, and the span of where that pattern was declared.
         pattern: Spanned<Pattern>,
-        /// Can be `None` when it is expected outside the current file
-        expected_line: Option<NonZeroUsize>,
+        /// Where the diagnostic was expected.
+        expected_line: ExpectedLine,
+        /// Unmatched diagnostics that look like what the pattern was meant to match,
+        /// as their line, level and message, most likely first.
+        near_misses: Vec<(NonZeroUsize, Level, String)>,
     },
     /// A diagnostic code matcher was declared but had no matching error.
     CodeNotFound {
         /// The code that was not found, and the span of where that code was declared.
         code: Spanned<String>,
-        /// Can be `None` when it is expected outside the current file
-        expected_line: Option<NonZeroUsize>,
+        /// Where the diagnostic was expected.
+        expected_line: ExpectedLine,
+    },
+    /// A pattern or code with an explicit count (`//~ ERROR[x5]: msg`) matched
+    /// a different number of diagnostics.
+    WrongMatchCount {
+        /// The span of the pattern or code that declared the count.
+        span: Span,
+        /// Where the diagnostics were expected.
+        expected_line: ExpectedLine,
+        /// The number of diagnostics the annotation asked for.
+        expected: NonZeroUsize,
+        /// The number of matching diagnostics that were found on the line.
+        actual: usize,
     },
     /// A ui test checking for failure does not have any failure patterns
     NoPatternsFound,
@@ -52,6 +68,9 @@ pub enum Error {
         expected: Vec<u8>,
         /// A command, that when run, causes the output to get blessed instead of erroring.
         bless_command: Option<String>,
+        /// The normalization rules that were applied, in order.
+        /// Only recorded if [`Config::trace_normalization`](crate::Config::trace_normalization) is set.
+        normalization: Vec<RuleApplication>,
     },
     /// There were errors that don't have a pattern.
     ErrorsWithoutPattern {
@@ -97,3 +116,24 @@ pub enum Error {
 }
 
 pub(crate) type Errors = Vec<Error>;
+
+/// Where a pattern expected to find the diagnostics it matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpectedLine {
+    /// A specific line of the test file.
+    Line(NonZeroUsize),
+    /// Any line of the test file (`//~?` annotations).
+    Anywhere,
+    /// Outside the test file (`//@error-in-other-file` annotations).
+    OtherFile,
+}
+
+impl std::fmt::Display for ExpectedLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpectedLine::Line(line) => write!(f, "on line {line}"),
+            ExpectedLine::Anywhere => write!(f, "anywhere in the testfile"),
+            ExpectedLine::OtherFile => write!(f, "outside the testfile"),
+        }
+    }
+}
@@ -3,6 +3,7 @@
 use crate::display;
 use bstr::ByteSlice;
 use regex::bytes::{Captures, Regex};
+use spanned::Span;
 use std::borrow::Cow;
 use std::path::Path;
 use std::sync::OnceLock;
@@ -16,13 +17,93 @@ pub enum Match {
     Exact(Vec<u8>),
     /// Uses a heuristic to find backslashes in windows style paths
     PathBackslash,
+    /// Paths to the standard library sources, either in the sysroot
+    /// (`.../lib/rustlib/src/rust/library`) or remapped (`/rustc/<commit>/library`).
+    /// Replaced with `$SRC_DIR` by `//@normalize: sysroot`.
+    SysrootPaths,
+    /// The directory containing the test file.
+    /// Replaced with `$DIR` by `//@normalize: test-dir`.
+    TestDir,
+    /// The hashes rustc appends to symbol names and to the file names of build artifacts.
+    /// Replaced with `HASH` by `//@normalize: rustc-hash`.
+    RustcHash,
+    /// Line and column numbers in paths into the standard library, which change between
+    /// compiler versions. Apply after [`Match::SysrootPaths`].
+    /// Replaced with `LL:COL` by `//@normalize: std-line-numbers`.
+    StdLineNumbers,
+    /// The number in `N previous errors` and `N warnings emitted`.
+    /// Replaced with `NN` by `//@normalize: error-counts`.
+    ErrorCounts,
 }
 
+/// The names of the built-in normalizations usable with `//@normalize: name`
+/// and [`Config::builtin_filter`](crate::Config::builtin_filter).
+pub const BUILTIN_FILTERS: &[&str] = &[
+    "sysroot",
+    "test-dir",
+    "rustc-hash",
+    "std-line-numbers",
+    "error-counts",
+];
+
 impl Match {
-    pub(crate) fn replace_all<'a>(&self, text: &'a [u8], replacement: &[u8]) -> Cow<'a, [u8]> {
-        match self {
-            Match::Regex(regex) => regex.replace_all(text, replacement),
-            Match::Exact(needle) => text.replace(needle, replacement).into(),
+    /// Looks up a built-in normalization by name (see [`BUILTIN_FILTERS`])
+    /// and returns it together with its default replacement.
+    pub fn builtin(name: &str) -> Option<(Self, &'static [u8])> {
+        Some(match name {
+            "sysroot" => (Match::SysrootPaths, b"$SRC_DIR"),
+            "test-dir" => (Match::TestDir, b"$DIR"),
+            "rustc-hash" => (Match::RustcHash, b"HASH"),
+            "std-line-numbers" => (Match::StdLineNumbers, b"LL:COL"),
+            "error-counts" => (Match::ErrorCounts, b"NN"),
+            _ => return None,
+        })
+    }
+
+    /// Replaces all matches in `text`. `test_path` is the path of the test
+    /// whose output is being normalized. Also returns the number of replacements.
+    pub(crate) fn replace_all<'a>(
+        &self,
+        text: &'a [u8],
+        replacement: &[u8],
+        test_path: &Path,
+    ) -> (Cow<'a, [u8]>, usize) {
+        let mut count = 0;
+        /// Replaces only the capture group `group` of every match.
+        fn replace_group<'a>(
+            regex: &Regex,
+            text: &'a [u8],
+            group: &str,
+            replacement: &[u8],
+            count: &mut usize,
+        ) -> Cow<'a, [u8]> {
+            regex.replace_all(text, |caps: &Captures<'_>| {
+                *count += 1;
+                let all = caps.get(0).unwrap();
+                let group = caps.name(group).unwrap();
+                let mut replaced = all.as_bytes()[..group.start() - all.start()].to_vec();
+                replaced.extend_from_slice(replacement);
+                replaced.extend_from_slice(&all.as_bytes()[group.end() - all.start()..]);
+                replaced
+            })
+        }
+        macro_rules! regex {
+            ($re:literal) => {{
+                static RE: OnceLock<Regex> = OnceLock::new();
+                RE.get_or_init(|| Regex::new($re).unwrap())
+            }};
+        }
+        let text = match self {
+            Match::Regex(regex) => regex.replace_all(text, |caps: &Captures<'_>| {
+                count += 1;
+                let mut replaced = vec![];
+                caps.expand(replacement, &mut replaced);
+                replaced
+            }),
+            Match::Exact(needle) => {
+                count = text.find_iter(needle).count();
+                text.replace(needle, replacement).into()
+            }
             Match::PathBackslash => {
                 static PATH_RE: OnceLock<Regex> = OnceLock::new();
                 PATH_RE
@@ -40,13 +121,101 @@ impl Match {
                         .unwrap()
                     })
                     .replace_all(text, |caps: &Captures<'_>| {
+                        count += 1;
                         caps[0].replace(r"\", replacement)
                     })
             }
+            Match::SysrootPaths => regex!(
+                r#"(?:[^ \t\r\n"'`(<\[]*[/\\]lib[/\\]rustlib[/\\]src[/\\]rust|[/\\]rustc[/\\][0-9a-f]{40})[/\\]library"#
+            )
+            .replace_all(text, |_: &Captures<'_>| {
+                count += 1;
+                replacement
+            }),
+            Match::TestDir => {
+                let Some(dir) = test_path.parent().filter(|dir| !dir.as_os_str().is_empty())
+                else {
+                    return (text.into(), 0);
+                };
+                let mut text = Cow::Borrowed(text);
+                // Replace the canonical (absolute) form first, as it contains the relative one.
+                for dir in dir.canonicalize().ok().iter().map(|dir| &**dir).chain([dir]) {
+                    let Match::Exact(dir) = Match::from(dir) else {
+                        unreachable!()
+                    };
+                    // Only replace whole directories, not prefixes of other names.
+                    for separator in [b'/', b'\\'] {
+                        let needle = [&dir[..], &[separator]].concat();
+                        let found = text.find_iter(&needle).count();
+                        if found > 0 {
+                            count += found;
+                            let replacement = [replacement, &[separator]].concat();
+                            text = text.replace(&needle, replacement).into();
+                        }
+                    }
+                }
+                text
+            }
+            Match::RustcHash => replace_group(
+                regex!(r"(?:(?:::|17)h|-)(?P<hash>[0-9a-f]{16})(?:E|(?-u:\b))"),
+                text,
+                "hash",
+                replacement,
+                &mut count,
+            ),
+            Match::StdLineNumbers => replace_group(
+                regex!(r"(?:\$SRC_DIR|[/\\]library)[/\\][^ \t\r\n:]+\.rs:(?P<pos>[0-9]+:[0-9]+)"),
+                text,
+                "pos",
+                replacement,
+                &mut count,
+            ),
+            Match::ErrorCounts => replace_group(
+                regex!(r"(?-u:\b)(?P<count>[0-9]+) (?:previous errors?|warnings? emitted)"),
+                text,
+                "count",
+                replacement,
+                &mut count,
+            ),
+        };
+        (text, count)
+    }
+}
+
+impl std::fmt::Display for Match {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Match::Regex(regex) => write!(f, "`{}`", regex.as_str()),
+            Match::Exact(needle) => write!(f, "`{}`", needle.to_str_lossy()),
+            Match::PathBackslash => write!(f, "backslashes in paths"),
+            Match::SysrootPaths => write!(f, "sysroot"),
+            Match::TestDir => write!(f, "test-dir"),
+            Match::RustcHash => write!(f, "rustc-hash"),
+            Match::StdLineNumbers => write!(f, "std-line-numbers"),
+            Match::ErrorCounts => write!(f, "error-counts"),
         }
     }
 }
 
+/// How often a normalization rule replaced something in an output,
+/// recorded if [`Config::trace_normalization`](crate::Config::trace_normalization) is set.
+#[derive(Debug, Clone)]
+pub struct RuleApplication {
+    /// Where the rule was declared. Dummy for rules added via [`Config`](crate::Config).
+    pub span: Span,
+    /// The rule, rendered like ``"`regex` -> `replacement`"``.
+    pub rule: String,
+    /// The number of replacements. Rules that replaced nothing are dead.
+    pub replacements: usize,
+}
+
+impl RuleApplication {
+    /// Renders a rule like [`RuleApplication::rule`].
+    pub(crate) fn describe(from: &Match, to: &[u8]) -> String {
+        format!("{from} -> `{}`", to.to_str_lossy())
+    }
+}
+
 impl From<&'_ Path> for Match {
     fn from(v: &Path) -> Self {
         let mut v = display(v);
@@ -0,0 +1,139 @@
+//! Running tests whose sources are generated by the test harness instead of read from disk.
+
+use crate::{check_file, display, test_result::TestRun, Config, Match};
+use color_eyre::eyre::{bail, Context as _, Result};
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A test that only exists in memory, see [`check_in_memory`].
+#[derive(Debug, Clone)]
+pub struct InMemoryTest {
+    /// The relative path the test is reported under, e.g. `lints/foo_2018.rs`.
+    pub path: PathBuf,
+    /// The source of the test, including its `//@` commands and `//~` annotations.
+    pub source: Vec<u8>,
+    /// The expected output files, keyed by output kind (e.g. `stderr`, `run.stdout` or `fixed`).
+    /// Kinds without an entry are expected to produce no output.
+    /// [`check_in_memory`] replaces them with the output files after the run.
+    pub snapshots: BTreeMap<String, Vec<u8>>,
+}
+
+/// Run tests that only exist in memory, one after the other, like [`check_file`] does.
+///
+/// The compiler and the output checks need the files on disk, so the sources and
+/// expected outputs are written to a fresh directory in [`Config::out_dir`], which is
+/// removed again afterwards. That directory is normalized away in stderr and stdout, so
+/// diagnostics refer to the relative [`InMemoryTest::path`].
+/// Afterwards, the [`InMemoryTest::snapshots`] hold the output files as the tests left them,
+/// so when blessing they contain the updated outputs.
+/// [`Error::OutputDiffers`](crate::Error::OutputDiffers) also contains the actual output.
+pub fn check_in_memory(mut config: Config, tests: &mut [InMemoryTest]) -> Result<Vec<TestRun>> {
+    for test in tests.iter() {
+        if !test
+            .path
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            bail!(
+                "in-memory test paths must be relative and stay within their directory: {}",
+                display(&test.path)
+            );
+        }
+    }
+
+    // Every call gets a directory of its own, so calls don't see each other's files.
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+    let dir = config.out_dir.join("in_memory").join(format!(
+        "{}_{}",
+        std::process::id(),
+        CALLS.fetch_add(1, Ordering::Relaxed)
+    ));
+    config.root_dir.clone_from(&dir);
+    let base = config.comment_defaults.base();
+    for normalizations in [&mut base.normalize_stderr, &mut base.normalize_stdout] {
+        // `join("")` adds a trailing path separator.
+        normalizations.push((Match::from(&*dir.join("")), vec![]));
+    }
+
+    let runs = run_in(&config, &dir, tests);
+    let removed = match std::fs::remove_dir_all(&dir) {
+        Err(err) if err.kind() != ErrorKind::NotFound => {
+            Err(err).wrap_err_with(|| format!("failed to remove {}", display(&dir)))
+        }
+        _ => Ok(()),
+    };
+    let runs = runs?;
+    removed?;
+    Ok(runs)
+}
+
+fn run_in(config: &Config, dir: &Path, tests: &mut [InMemoryTest]) -> Result<Vec<TestRun>> {
+    let mut paths = vec![];
+    for test in tests.iter() {
+        let path = dir.join(&test.path);
+        std::fs::create_dir_all(path.parent().unwrap())
+            .wrap_err_with(|| format!("failed to create directory for {}", display(&path)))?;
+        std::fs::write(&path, &test.source)
+            .wrap_err_with(|| format!("failed to write {}", display(&path)))?;
+        for (kind, snapshot) in &test.snapshots {
+            let snapshot_path = path.with_extension(kind);
+            std::fs::write(&snapshot_path, snapshot)
+                .wrap_err_with(|| format!("failed to write {}", display(&snapshot_path)))?;
+        }
+        paths.push(path);
+    }
+
+    let mut runs = vec![];
+    for path in &paths {
+        runs.extend(check_file(config.clone(), path)?);
+    }
+
+    for (test, path) in tests.iter_mut().zip(&paths) {
+        test.snapshots = read_snapshots(path, &paths)?;
+    }
+    Ok(runs)
+}
+
+/// Reads the output files next to the test at `path`, keyed by what follows the test's name.
+/// Files of the other tests in `paths`, like `foo.bar.rs` and its outputs next to `foo.rs`,
+/// are skipped.
+fn read_snapshots(path: &Path, paths: &[PathBuf]) -> Result<BTreeMap<String, Vec<u8>>> {
+    let dir = path.parent().unwrap();
+    let prefix = |path: &Path| format!("{}.", path.file_stem().unwrap().to_string_lossy());
+    let own_prefix = prefix(path);
+    // Tests in the same directory whose names start with this test's name.
+    let longer_prefixes: Vec<_> = paths
+        .iter()
+        .filter(|other| other.parent() == Some(dir))
+        .map(|other| prefix(other))
+        .filter(|other| other.len() > own_prefix.len() && other.starts_with(&own_prefix))
+        .collect();
+    let mut snapshots = BTreeMap::new();
+    for entry in
+        std::fs::read_dir(dir).wrap_err_with(|| format!("failed to read {}", display(dir)))?
+    {
+        let entry = entry.wrap_err_with(|| format!("failed to read {}", display(dir)))?;
+        let file_name = entry.file_name();
+        let Some(name) = file_name.to_str() else {
+            continue;
+        };
+        let Some(kind) = name.strip_prefix(&own_prefix) else {
+            continue;
+        };
+        if Some(&*file_name) == path.file_name()
+            || kind.ends_with(".rs")
+            || longer_prefixes.iter().any(|other| name.starts_with(other))
+            || !entry.path().is_file()
+        {
+            continue;
+        }
+        let snapshot = std::fs::read(entry.path())
+            .wrap_err_with(|| format!("failed to read {}", display(&entry.path())))?;
+        snapshots.insert(kind.to_owned(), snapshot);
+    }
+    Ok(snapshots)
+}
@@ -15,7 +15,6 @@ use build_manager::BuildManager;
 use build_manager::NewJob;
 pub use color_eyre;
 use color_eyre::eyre::eyre;
-#[cfg(feature = "rustc")]
 use color_eyre::eyre::Context as _;
 pub use color_eyre::eyre::Result;
 pub use core::run_and_collect;
@@ -30,16 +29,18 @@ use status_emitter::SilentStatus;
 use status_emitter::{StatusEmitter, TestStatus};
 use std::collections::VecDeque;
 use std::panic::AssertUnwindSafe;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 #[cfg(feature = "rustc")]
 use std::process::Command;
 use std::sync::Arc;
 use test_result::TestRun;
-pub use test_result::{Errored, TestOk};
+pub use test_result::{Errored, IgnoreReason, TestOk};
 
 pub mod aux_builds;
+mod bless_annotations;
 pub mod build_manager;
 mod cmd;
+pub mod compare;
 mod config;
 pub mod core;
 pub mod custom_flags;
@@ -51,6 +52,7 @@ mod error;
 pub mod filter;
 #[cfg(feature = "gha")]
 pub mod github_actions;
+mod in_memory;
 mod mode;
 pub mod nextest;
 mod parser;
@@ -64,9 +66,14 @@ mod tests;
 pub use cmd::*;
 pub use config::*;
 pub use error::*;
+pub use in_memory::{check_in_memory, InMemoryTest};
 pub use parser::*;
 pub use spanned;
 
+/// The name of the files whose `//@` comments are applied to all tests in the same
+/// directory and its subdirectories, in addition to [`Config::comment_defaults`].
+pub const DEFAULTS_FILE_NAME: &str = "defaults.ui";
+
 /// Run all tests as described in the config argument.
 /// Will additionally process command line arguments.
 pub fn run_tests(mut config: Config) -> Result<()> {
@@ -81,10 +88,20 @@ pub fn run_tests(mut config: Config) -> Result<()> {
     #[cfg(feature = "gha")]
     let name = display(&config.root_dir);
 
-    let text = match args.format {
+    let mut text = match args.format {
         Format::Terse => status_emitter::Text::quiet(),
         Format::Pretty => status_emitter::Text::verbose(),
     };
+    if args.show_ignored_reasons {
+        text = text.show_ignored_reasons();
+    }
+    text = text.diff_format(args.diff);
+    if let Some(context) = args.diff_context {
+        text = text.diff_context(context);
+    }
+    if let Some(tool) = &args.diff_tool {
+        text = text.diff_tool(tool.clone());
+    }
     config.with_args(&args);
 
     run_tests_generic(
@@ -168,6 +185,64 @@ pub fn test_command(mut config: Config, path: &Path) -> Result<Command> {
     Ok(config.build_command(&build_manager).unwrap())
 }
 
+/// Run a single test file with the settings from the `config` argument, without walking
+/// [`Config::root_dir`], spawning worker threads or emitting any output.
+/// Returns one [`TestRun`] per revision, followed by the runs of follow-up jobs like
+/// running the test's executable or checking the rustfix output.
+/// `defaults.ui` files in the directories between [`Config::root_dir`] and the file are applied.
+/// Like [`test_command`], this ignores the settings of `Config` that relate to finding test files,
+/// and does not apply any per-file configuration like [`default_per_file_config`].
+pub fn check_file(mut config: Config, path: &Path) -> Result<Vec<TestRun>> {
+    config.fill_host_and_target()?;
+
+    let file_contents = Spanned::read_from_file(path)
+        .wrap_err_with(|| format!("failed to read {}", display(path)))?;
+    let mut files: Vec<_> = path
+        .ancestors()
+        .skip(1)
+        .take_while(|dir| dir.starts_with(&config.root_dir))
+        .map(|dir| dir.join(DEFAULTS_FILE_NAME))
+        .filter(|file| file.is_file())
+        .collect();
+    // Apply the outermost defaults first.
+    files.reverse();
+    let defaults = DirDefaults {
+        files,
+        comments: None,
+    };
+
+    let abort_check = config.abort_check.clone();
+    let (submit, jobs) = crossbeam_channel::unbounded();
+    let build_manager = Arc::new(BuildManager::new(config, submit));
+    let status = Box::new(SilentStatus {
+        revision: String::new(),
+        path: path.to_path_buf(),
+    });
+    let mut runs = match parse_and_test_file(
+        build_manager.clone(),
+        status,
+        build_manager.config().clone(),
+        file_contents,
+        &defaults,
+        |_, _| {},
+    ) {
+        Ok(runs) => runs,
+        Err((status, err)) => vec![TestRun {
+            result: Err(err),
+            status,
+            abort_check,
+        }],
+    };
+
+    // Run the jobs that the tests queued up, which may queue up further jobs.
+    let (finished_sender, finished) = crossbeam_channel::unbounded();
+    while let Ok(job) = jobs.try_recv() {
+        job(&finished_sender)?;
+        runs.extend(finished.try_iter());
+    }
+    Ok(runs)
+}
+
 /// A version of `run_tests` that allows more fine-grained control over running tests.
 ///
 /// All `configs` are being run in parallel.
@@ -218,22 +293,33 @@ pub fn run_tests_generic(
                 todo.push_back((
                     build_manager.config().root_dir.clone(),
                     build_manager.clone(),
+                    Arc::new(DirDefaults {
+                        files: vec![],
+                        comments: Some(build_manager.config().comment_defaults.clone()),
+                    }),
                 ));
             }
-            while let Some((path, build_manager)) = todo.pop_front() {
+            while let Some((path, build_manager, defaults)) = todo.pop_front() {
                 if path.is_dir() {
                     if path.file_name().unwrap() == "auxiliary" {
                         continue;
                     }
+                    let defaults_file = path.join(DEFAULTS_FILE_NAME);
+                    let defaults = if defaults_file.is_file() {
+                        Arc::new(defaults.nested(build_manager.config(), defaults_file))
+                    } else {
+                        defaults
+                    };
                     // Enqueue everything inside this directory.
                     // We want it sorted, to have some control over scheduling of slow tests.
                     let mut entries = std::fs::read_dir(path)
                         .unwrap()
                         .map(|e| e.unwrap().path())
+                        .filter(|path| path.file_name().unwrap() != DEFAULTS_FILE_NAME)
                         .collect::<Vec<_>>();
                     entries.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
                     for entry in entries {
-                        todo.push_back((entry, build_manager.clone()));
+                        todo.push_back((entry, build_manager.clone(), defaults.clone()));
                     }
                 } else if let Some(matched) = file_filter(&path, build_manager.config()) {
                     if matched {
@@ -242,10 +328,10 @@ pub fn run_tests_generic(
                         submit
                             .send(Box::new(move |finished_files_sender: &Sender<TestRun>| {
                                 let file_contents = Spanned::read_from_file(&path).unwrap();
-                                let mut config = build_manager.config().clone();
+                                let config = build_manager.config().clone();
                                 let abort_check = config.abort_check.clone();
-                                per_file_config(&mut config, &file_contents);
                                 let status = AssertUnwindSafe(status);
+                                let per_file_config = AssertUnwindSafe(per_file_config);
                                 let result = std::panic::catch_unwind(|| {
                                     let status = status;
                                     parse_and_test_file(
@@ -253,6 +339,8 @@ pub fn run_tests_generic(
                                         status.0,
                                         config,
                                         file_contents,
+                                        &defaults,
+                                        *per_file_config,
                                     )
                                 });
                                 let result = match result {
@@ -342,7 +430,7 @@ pub fn run_tests_generic(
         aborted |= run.abort_check.aborted();
         match run.result {
             Ok(TestOk::Ok) => succeeded += 1,
-            Ok(TestOk::Ignored) => ignored += 1,
+            Ok(TestOk::Ignored(_)) => ignored += 1,
             Err(errored) => failures.push((run.status, errored)),
         }
     }
@@ -370,12 +458,71 @@ pub fn run_tests_generic(
     }
 }
 
+/// The `defaults.ui` files that apply to the tests in a directory, outermost first.
+struct DirDefaults {
+    files: Vec<PathBuf>,
+    /// The comments of all `files` merged into [`Config::comment_defaults`], parsed once for
+    /// all tests in the directory. `None` if they could not be parsed, or were not parsed ahead
+    /// of time; each test then parses the files itself and reports the errors.
+    comments: Option<Comments>,
+}
+
+impl DirDefaults {
+    /// The defaults of a subdirectory containing the `defaults.ui` file at `path`.
+    fn nested(&self, config: &Config, path: PathBuf) -> Self {
+        let comments = self.comments.as_ref().and_then(|comments| {
+            let mut config = config.clone();
+            config.comment_defaults = comments.clone();
+            load_defaults(&mut config, std::slice::from_ref(&path)).ok()?;
+            Some(config.comment_defaults)
+        });
+        let mut files = self.files.clone();
+        files.push(path);
+        Self { files, comments }
+    }
+
+    fn apply(&self, config: &mut Config) -> Result<(), Errored> {
+        match &self.comments {
+            Some(comments) => {
+                config.comment_defaults = comments.clone();
+                Ok(())
+            }
+            None => load_defaults(config, &self.files),
+        }
+    }
+}
+
+/// Merges the comments of the `defaults.ui` files into [`Config::comment_defaults`], in order.
+fn load_defaults(config: &mut Config, files: &[PathBuf]) -> Result<(), Errored> {
+    for path in files {
+        let content = Spanned::read_from_file(path).map_err(|err| {
+            Errored::new(
+                vec![Error::ConfigError(format!(
+                    "failed to read {}: {err}",
+                    display(path)
+                ))],
+                "read defaults",
+            )
+        })?;
+        config.comment_defaults = Comments::parse(content.as_ref(), config)
+            .map_err(|errors| Errored::new(errors, "parse comments"))?;
+    }
+    Ok(())
+}
+
 fn parse_and_test_file(
     build_manager: Arc<BuildManager>,
     status: Box<dyn TestStatus>,
-    config: Config,
+    mut config: Config,
     file_contents: Spanned<Vec<u8>>,
+    defaults: &DirDefaults,
+    per_file_config: impl Fn(&mut Config, &Spanned<Vec<u8>>),
 ) -> Result<Vec<TestRun>, (Box<dyn TestStatus>, Errored)> {
+    // Merge the defaults of all directories from the root to the test file.
+    if let Err(err) = defaults.apply(&mut config) {
+        return Err((status, err));
+    }
+    per_file_config(&mut config, &file_contents);
     let comments = match Comments::parse(file_contents.as_ref(), &config) {
         Ok(t) => t,
         Err(errors) => return Err((status, Errored::new(errors, "parse comments"))),
@@ -409,9 +556,12 @@ fn test_file(
     runs: &mut Vec<TestRun>,
     build_manager: &Arc<BuildManager>,
 ) {
-    if !config.test_file_conditions(comments, status.revision()) {
+    if let Some(reason) = config
+        .ignore_reason(comments, status.revision())
+        .transpose()
+    {
         runs.push(TestRun {
-            result: Ok(TestOk::Ignored),
+            result: reason.map(TestOk::Ignored),
             status,
             abort_check: config.abort_check.clone(),
         });
@@ -1,13 +1,19 @@
 use crate::{
-    custom_flags::Flag, diagnostics::Level, filter::Match, test_result::Errored, Config, Error,
+    compare::Comparison,
+    custom_flags::Flag,
+    diagnostics::Level,
+    filter::{Match, RuleApplication, BUILTIN_FILTERS},
+    test_result::Errored,
+    Config, Error,
 };
 use bstr::{ByteSlice, Utf8Error};
 use color_eyre::eyre::Result;
 use regex::bytes::Regex;
 pub use spanned::*;
 use std::{
-    collections::{BTreeMap, HashMap},
+    collections::{BTreeMap, HashMap, HashSet},
     num::NonZeroUsize,
+    path::{Path, PathBuf},
 };
 
 mod spanned;
@@ -121,10 +127,16 @@ pub struct Revisioned {
     pub span: Span,
     /// Don't run this test if any of these filters apply
     pub ignore: Vec<Condition>,
+    /// Where the `ignore` filters were declared, in the same order.
+    pub(crate) ignore_spans: Vec<Span>,
     /// Only run this test if all of these filters apply
     pub only: Vec<Condition>,
+    /// Where the `only` filters were declared, in the same order.
+    pub(crate) only_spans: Vec<Span>,
     /// Generate one .stderr file per bit width, by prepending with `.64bit` and similar
     pub stderr_per_bitwidth: bool,
+    /// Revisions share the output files of the first revision unless their output differs.
+    pub shared_snapshot: bool,
     /// Additional flags to pass to the executable
     pub compile_flags: Vec<String>,
     /// Additional env vars to set for the executable
@@ -133,6 +145,16 @@ pub struct Revisioned {
     pub normalize_stderr: Vec<(Match, Vec<u8>)>,
     /// Normalizations to apply to the stdout output before emitting it to disk
     pub normalize_stdout: Vec<(Match, Vec<u8>)>,
+    /// Normalizations for other kinds of output, keyed by output kind (e.g. `fixed` or `run.stdout`).
+    /// They are applied after `normalize_stderr` and `normalize_stdout`.
+    /// See [`Revisioned::normalizations`] for which outputs a kind applies to.
+    pub normalize_outputs: BTreeMap<String, Normalizations>,
+    /// Where the normalizations added by `//@` comments were declared, keyed by the rule as
+    /// rendered in [`RuleApplication::rule`].
+    pub(crate) normalization_spans: Vec<(String, Span)>,
+    /// How to compare outputs to their output files, keyed by output kind like the
+    /// normalizations. Set via `//@compare-stderr: unordered` and similar.
+    pub compare: BTreeMap<String, Spanned<Comparison>>,
     /// Arbitrary patterns to look for in the stderr.
     /// The error must be from another file, as errors from the current file must be
     /// checked via `error_matches`.
@@ -185,8 +207,56 @@ impl Revisioned {
         self.custom
             .insert(key, Spanned::dummy(vec![Box::new(custom)]));
     }
+
+    /// Adds an `only` filter that was declared at `span`.
+    fn push_only(&mut self, cond: Condition, span: Span) {
+        self.only.push(cond);
+        self.only_spans.push(span);
+    }
+
+    /// Adds a normalization that was declared at `span`.
+    fn push_normalization(&mut self, kind: &str, from: Match, to: Vec<u8>, span: Span) {
+        self.normalization_spans
+            .push((RuleApplication::describe(&from, &to), span));
+        self.normalizations_mut(kind).push((from, to));
+    }
+
+    /// The normalizations for an output kind, to add new ones.
+    pub fn normalizations_mut(&mut self, kind: &str) -> &mut Normalizations {
+        match kind {
+            "stderr" => &mut self.normalize_stderr,
+            "stdout" => &mut self.normalize_stdout,
+            _ => self.normalize_outputs.entry(kind.to_owned()).or_default(),
+        }
+    }
+
+    /// The normalizations that apply to an output of the given kind.
+    /// Normalizations registered for a kind also apply to all more specific kinds,
+    /// so `stderr` normalizations apply to `run.stderr`, and `fixed` ones to `1.fixed`.
+    pub fn normalizations<'a>(
+        &'a self,
+        kind: &'a str,
+    ) -> impl Iterator<Item = &'a (Match, Vec<u8>)> + 'a {
+        [
+            ("stderr", &self.normalize_stderr),
+            ("stdout", &self.normalize_stdout),
+        ]
+        .into_iter()
+        .chain(self.normalize_outputs.iter().map(|(k, v)| (&**k, v)))
+        .filter(move |(key, _)| output_kind_applies(key, kind))
+        .flat_map(|(_, normalizations)| normalizations)
+    }
+}
+
+/// Whether settings for the output kind `key` apply to outputs of kind `kind`,
+/// i.e. whether `kind` is `key` or ends in `.{key}`.
+pub(crate) fn output_kind_applies(key: &str, kind: &str) -> bool {
+    kind == key || kind.strip_suffix(key).is_some_and(|k| k.ends_with('.'))
 }
 
+/// Normalization rules: what to replace, and the replacement.
+pub type Normalizations = Vec<(Match, Vec<u8>)>;
+
 /// Main entry point to parsing comments and handling parsing errors.
 #[derive(Debug)]
 pub struct CommentParser<T> {
@@ -198,6 +268,13 @@ pub struct CommentParser<T> {
     commands: HashMap<&'static str, CommandParserFunc>,
     /// The symbol(s) that signify the start of a comment.
     comment_start: &'static str,
+    /// The files currently being parsed, innermost `//@include` last.
+    includes: Vec<PathBuf>,
+    /// The comparisons selectable via `//@compare-{kind}: name`, see [`Config::comparisons`].
+    comparisons: BTreeMap<&'static str, Comparison>,
+    /// The output kinds accepted by `//@normalize-{kind}` and `//@compare-{kind}`,
+    /// see [`Config::output_kinds`].
+    output_kinds: Vec<&'static str>,
 }
 
 /// Command parser function type.
@@ -229,6 +306,16 @@ pub enum Condition {
     Bitwidth(Vec<u8>),
     /// Tests that the target is the host.
     OnHost,
+    /// Tests that the compiler is from the given release channel.
+    Channel(rustc_version::Channel),
+    /// Tests that the compiler version is at least the given one.
+    MinRustcVersion(rustc_version::Version),
+    /// Tests that the compiler version is at most the given one.
+    MaxRustcVersion(rustc_version::Version),
+    /// A `cfg` expression like `all(unix, target_os = "linux")`, evaluated
+    /// against the `--print=cfg` output for the target.
+    #[cfg(feature = "rustc")]
+    Cfg(cargo_platform::CfgExpr),
 }
 
 /// A sub string of a target (or a whole target).
@@ -268,6 +355,24 @@ impl TryFrom<String> for TargetSubStr {
     }
 }
 
+/// Parses a compiler version like `1.80` or `1.80.1`, missing components default to `0`.
+fn parse_rustc_version(s: &str) -> std::result::Result<rustc_version::Version, String> {
+    let mut parts = s.split('.').map(|part| {
+        part.parse::<u64>().map_err(|_| {
+            format!("`{s}` is not a valid compiler version, expected `MAJOR.MINOR(.PATCH)`")
+        })
+    });
+    let major = parts.next().unwrap()?;
+    let minor = parts.next().transpose()?.unwrap_or(0);
+    let patch = parts.next().transpose()?.unwrap_or(0);
+    if parts.next().is_some() {
+        return Err(format!(
+            "`{s}` is not a valid compiler version, expected `MAJOR.MINOR(.PATCH)`"
+        ));
+    }
+    Ok(rustc_version::Version::new(major, minor, patch))
+}
+
 /// An error pattern parsed from a `//~` comment.
 #[derive(Debug, Clone)]
 pub enum Pattern {
@@ -284,8 +389,9 @@ pub(crate) enum ErrorMatchKind {
         pattern: Spanned<Pattern>,
         level: Level,
     },
-    /// An error code parsed from a `//~ error_code` comment.
-    Code(Spanned<String>),
+    /// A level and diagnostic code pair parsed from a `//~ LEVEL error_code` comment.
+    /// A plain `//~ error_code` comment defaults to the `ERROR` level.
+    Code { code: Spanned<String>, level: Level },
 }
 
 #[derive(Debug, Clone)]
@@ -293,13 +399,35 @@ pub(crate) struct ErrorMatch {
     pub(crate) kind: ErrorMatchKind,
     /// The line this pattern is expecting to find a message in.
     pub(crate) line: NonZeroUsize,
+    /// The exact number of messages this pattern must match, parsed from a `[xN]` suffix.
+    /// `None` means the pattern consumes a single message.
+    pub(crate) count: Option<NonZeroUsize>,
+    /// Parsed from `//~?`: the pattern may match a message on any line of the file.
+    /// `line` is then the line of the annotation itself.
+    pub(crate) anywhere: bool,
 }
 
 impl Condition {
     fn parse(c: &str, args: &str) -> std::result::Result<Self, String> {
+        #[cfg(feature = "rustc")]
+        if c == "cfg" {
+            return args
+                .trim()
+                .parse()
+                .map(Condition::Cfg)
+                .map_err(|err| format!("invalid cfg expression: {err}"));
+        }
+        #[cfg(not(feature = "rustc"))]
+        if c == "cfg" {
+            return Err("`cfg` conditions require the `rustc` feature of `ui_test`".into());
+        }
         let args = args.split_whitespace();
         match c {
             "on-host" => Ok(Condition::OnHost),
+            "stable" => Ok(Condition::Channel(rustc_version::Channel::Stable)),
+            "beta" => Ok(Condition::Channel(rustc_version::Channel::Beta)),
+            "nightly" => Ok(Condition::Channel(rustc_version::Channel::Nightly)),
+            "dev" => Ok(Condition::Channel(rustc_version::Channel::Dev)),
             "bitwidth" => {
                 let bits = args.map(|arg| arg.parse::<u8>().map_err(|_err| {
                     format!("invalid ignore/only filter ending in 'bit': {c:?} is not a valid bitwdith")
@@ -308,11 +436,60 @@ impl Condition {
             }
             "target" => Ok(Condition::Target(args.take_while(|&arg| arg != "#").map(|arg|TargetSubStr::try_from(arg.to_owned())).collect::<Result<_, _>>()?)),
             "host" => Ok(Condition::Host(args.take_while(|&arg| arg != "#").map(|arg|TargetSubStr::try_from(arg.to_owned())).collect::<Result<_, _>>()?)),
-            _ => Err(format!("`{c}` is not a valid condition, expected `on-host`, /[0-9]+bit/, /host-.*/, or /target-.*/")),
+            _ => Err(format!("`{c}` is not a valid condition, expected `on-host`, /[0-9]+bit/, /host-.*/, /target-.*/, `cfg`, or a release channel")),
+        }
+    }
+}
+
+/// Renders the condition the way it is written after `ignore-` or `only-`.
+impl std::fmt::Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fn list(
+            f: &mut std::fmt::Formatter<'_>,
+            items: impl IntoIterator<Item = impl std::fmt::Display>,
+        ) -> std::fmt::Result {
+            for item in items {
+                write!(f, " {item}")?;
+            }
+            Ok(())
+        }
+        match self {
+            Condition::Host(host) => {
+                write!(f, "host:")?;
+                list(f, host.iter().map(|host| &**host))
+            }
+            Condition::Target(target) => {
+                write!(f, "target:")?;
+                list(f, target.iter().map(|target| &**target))
+            }
+            Condition::Bitwidth(bits) => {
+                write!(f, "bitwidth:")?;
+                list(f, bits)
+            }
+            Condition::OnHost => write!(f, "on-host"),
+            Condition::Channel(channel) => write!(
+                f,
+                "{}",
+                match channel {
+                    rustc_version::Channel::Dev => "dev",
+                    rustc_version::Channel::Nightly => "nightly",
+                    rustc_version::Channel::Beta => "beta",
+                    rustc_version::Channel::Stable => "stable",
+                }
+            ),
+            Condition::MinRustcVersion(version) => write!(f, "min-rustc-version: {version}"),
+            Condition::MaxRustcVersion(version) => write!(f, "max-rustc-version: {version}"),
+            #[cfg(feature = "rustc")]
+            Condition::Cfg(expr) => write!(f, "cfg: {expr}"),
         }
     }
 }
 
+/// Used to detect `//@include` cycles independently of how the paths were spelled.
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
 enum ParsePatternResult {
     Other,
     ErrorAbove {
@@ -346,6 +523,9 @@ impl CommentParser<Comments> {
             errors: vec![],
             commands: Self::commands(),
             comment_start: config.comment_start,
+            includes: vec![],
+            comparisons: config.comparisons.clone(),
+            output_kinds: config.output_kinds.clone(),
         };
         this.commands
             .extend(config.custom_comments.iter().map(|(&k, &v)| (k, v)));
@@ -357,6 +537,8 @@ impl CommentParser<Comments> {
         // by checking that they haven't already been set.
         let mut defaults = std::mem::take(self.comments.revisioned.get_mut(&[][..]).unwrap());
 
+        self.includes.push(canonical(&content.span().file));
+
         let mut delayed_fallthrough = Vec::new();
         let mut fallthrough_to = None; // The line that a `|` will refer to.
         let mut last_line = 0;
@@ -424,7 +606,7 @@ impl CommentParser<Comments> {
                 if m.line.get() > last_line {
                     let span = match &m.kind {
                         ErrorMatchKind::Pattern { pattern, .. } => pattern.span(),
-                        ErrorMatchKind::Code(code) => code.span(),
+                        ErrorMatchKind::Code { code, .. } => code.span(),
                     };
                     self.errors.push(Error::InvalidComment {
                         msg: format!(
@@ -445,12 +627,18 @@ impl CommentParser<Comments> {
         let Revisioned {
             span,
             ignore,
+            ignore_spans,
             only,
+            only_spans,
             stderr_per_bitwidth,
+            shared_snapshot,
             compile_flags,
             env_vars,
             normalize_stderr,
             normalize_stdout,
+            normalize_outputs,
+            normalization_spans,
+            compare,
             error_in_other_files,
             error_matches,
             require_annotations_for_level,
@@ -467,12 +655,23 @@ impl CommentParser<Comments> {
             *span = base.span;
         }
         ignore.extend(base.ignore);
+        ignore_spans.extend(base.ignore_spans);
         only.extend(base.only);
+        only_spans.extend(base.only_spans);
         *stderr_per_bitwidth |= base.stderr_per_bitwidth;
+        *shared_snapshot |= base.shared_snapshot;
         compile_flags.extend(base.compile_flags);
         env_vars.extend(base.env_vars);
         normalize_stderr.extend(base.normalize_stderr);
         normalize_stdout.extend(base.normalize_stdout);
+        for (kind, normalizations) in base.normalize_outputs {
+            normalize_outputs
+                .entry(kind)
+                .or_default()
+                .extend(normalizations);
+        }
+        normalization_spans.extend(base.normalization_spans);
+        compare.extend(base.compare);
         error_in_other_files.extend(base.error_in_other_files);
         error_matches.extend(base.error_matches);
         if base.require_annotations_for_level.is_some() {
@@ -519,7 +718,18 @@ impl CommentParser<Comments> {
                     _ => false,
                 })
         {
-            if let Some(command) = comment.strip_prefix(b"@") {
+            if comment.starts_with(b"@@") || comment.starts_with(b"~~") {
+                let (sigil, _) = comment.to_str()?.split_at(2);
+                self.error(
+                    sigil.span(),
+                    format!(
+                        "`{0}{1}` is not a valid test suite comment, did you mean `{0}{2}`?",
+                        self.comment_start,
+                        *sigil,
+                        &sigil[1..],
+                    ),
+                );
+            } else if let Some(command) = comment.strip_prefix(b"@") {
                 self.parse_command(command.to_str()?.trim())
             } else if let Some(pattern) = comment.strip_prefix(b"~") {
                 let (revisions, pattern) = self.parse_revisions(pattern.to_str()?);
@@ -532,7 +742,16 @@ impl CommentParser<Comments> {
         } else {
             for pos in line.clone().find_iter(self.comment_start) {
                 let (_, rest) = line.clone().to_str()?.split_at(pos + 2);
-                for rest in std::iter::once(rest.clone()).chain(rest.strip_prefix(" ")) {
+                let trimmed = rest.clone().trim_start();
+                // Any amount of whitespace before an `@` still looks like a command,
+                // but other sigils are only suspicious directly after the comment start.
+                let indented_command = (trimmed.len() + 1 < rest.len()
+                    && trimmed.content.starts_with('@'))
+                .then_some(trimmed);
+                for rest in std::iter::once(rest.clone())
+                    .chain(rest.strip_prefix(" "))
+                    .chain(indented_command)
+                {
                     let c = rest.chars().next();
                     if let Some(Spanned {
                         content: '@' | '~' | '[' | ']' | '^' | '|',
@@ -554,6 +773,9 @@ impl CommentParser<Comments> {
                             comments: Comments::default(),
                             commands: std::mem::take(&mut self.commands),
                             comment_start: self.comment_start,
+                            includes: vec![],
+                            comparisons: std::mem::take(&mut self.comparisons),
+                            output_kinds: std::mem::take(&mut self.output_kinds),
                         };
                         let span = rest.span();
                         parser.parse_command(rest);
@@ -569,6 +791,8 @@ impl CommentParser<Comments> {
                             );
                         }
                         self.commands = parser.commands;
+                        self.comparisons = parser.comparisons;
+                        self.output_kinds = parser.output_kinds;
                     }
                 }
             }
@@ -644,9 +868,163 @@ impl CommentParser<Comments> {
             self.revisions = Some(args.split_whitespace().map(|s| s.to_string()).collect());
             return;
         }
+        if *command == "revisions-matrix" {
+            self.check(
+                revisions.span(),
+                revisions.is_empty(),
+                "revisions cannot be declared under a revision",
+            );
+            self.check(
+                revisions.span(),
+                self.revisions.is_none(),
+                "cannot specify `revisions` twice",
+            );
+            self.revisions = self.parse_revisions_matrix(args);
+            return;
+        }
+        if *command == "include" {
+            self.check(
+                revisions.span(),
+                revisions.is_empty(),
+                "`include` cannot be used under a revision",
+            );
+            self.parse_include(args);
+            return;
+        }
         self.revisioned(revisions, |this| this.parse_command(command, args));
     }
 
+    /// Splices the `//@` commands of another file into the current test.
+    /// The path is relative to the file containing the `//@include`.
+    fn parse_include(&mut self, args: Spanned<&str>) {
+        let span = args.span();
+        let path = span.file.parent().unwrap_or(Path::new("")).join(*args);
+        let content = match Spanned::read_from_file(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                self.error(
+                    span,
+                    format!("could not read included file `{}`: {err}", path.display()),
+                );
+                return;
+            }
+        };
+        let canonical_path = canonical(&path);
+        if self.includes.contains(&canonical_path) {
+            let cycle = self
+                .includes
+                .iter()
+                .chain([&canonical_path])
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            self.error(span, format!("include cycle detected: {cycle}"));
+            return;
+        }
+        self.includes.push(canonical_path);
+        let content: Spanned<&[u8]> = content.as_ref();
+        for line in content.lines() {
+            let Some((pre, comment)) = line.split_once_str(self.comment_start) else {
+                continue;
+            };
+            let comment = match comment.to_str() {
+                Ok(comment) => comment,
+                Err(e) => {
+                    self.error(e.span, format!("Comment is not utf8: {:?}", e.content));
+                    continue;
+                }
+            };
+            if let Some(command) = comment.strip_prefix("@") {
+                if pre.is_empty() {
+                    self.parse_command(command.trim());
+                } else if pre.iter().all(u8::is_ascii_whitespace) {
+                    // Report indented commands instead of silently dropping them.
+                    self.error(
+                        comment.span(),
+                        format!(
+                            "All `{}@` test suite commands must be at the start of the line.",
+                            self.comment_start
+                        ),
+                    );
+                }
+            } else if comment.starts_with("~") {
+                self.error(
+                    comment.span(),
+                    "error annotations are not supported in included files",
+                );
+            }
+        }
+        self.includes.pop();
+    }
+
+    /// Parses `a=[x,y] x b=[z,w]` into one revision per combination of the
+    /// axis values, named like `a_x__b_z`. Revision names are passed to `--cfg`,
+    /// so they must stay valid identifiers.
+    fn parse_revisions_matrix(&mut self, args: Spanned<&str>) -> Option<Vec<String>> {
+        let mut revisions = vec![String::new()];
+        let mut axes: Vec<&str> = vec![];
+        for axis in args.split(" x ") {
+            let axis = axis.trim();
+            let parsed = axis.split_once('=').and_then(|(name, values)| {
+                let values = values.trim().strip_prefix('[')?.strip_suffix(']')?;
+                Some((
+                    name.trim(),
+                    values.split(',').map(str::trim).collect::<Vec<_>>(),
+                ))
+            });
+            let Some((name, values)) = parsed else {
+                self.error(
+                    args.span(),
+                    format!("revision matrix axis `{axis}` must look like `name=[value1,value2]`"),
+                );
+                return None;
+            };
+            let valid = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric());
+            if !valid(name)
+                || name.starts_with(|c: char| c.is_ascii_digit())
+                || !values.iter().all(|value| valid(value))
+            {
+                self.error(
+                    args.span(),
+                    format!("revision matrix axis `{axis}` must only use ascii letters and digits, and its name must start with a letter"),
+                );
+                return None;
+            }
+            if axes.contains(&name) {
+                self.error(
+                    args.span(),
+                    format!("revision matrix axis `{name}` is specified twice"),
+                );
+                return None;
+            }
+            if let Some((_, value)) = values
+                .iter()
+                .enumerate()
+                .find(|&(i, value)| values[..i].contains(value))
+            {
+                self.error(
+                    args.span(),
+                    format!("revision matrix axis `{name}` has the value `{value}` twice"),
+                );
+                return None;
+            }
+            axes.push(name);
+            revisions = revisions
+                .iter()
+                .flat_map(|prefix| {
+                    values.iter().map(move |value| {
+                        if prefix.is_empty() {
+                            format!("{name}_{value}")
+                        } else {
+                            format!("{prefix}__{name}_{value}")
+                        }
+                    })
+                })
+                .collect();
+        }
+        Some(revisions)
+    }
+
     fn revisioned(
         &mut self,
         revisions: Spanned<Vec<String>>,
@@ -656,8 +1034,49 @@ impl CommentParser<Comments> {
             content: revisions,
             span,
         } = revisions;
+        // Axis values of a revision matrix like `edition=2021` select all revisions containing them.
+        let mut expanded = vec![];
+        for revision in &revisions {
+            let Some((axis, value)) = revision.split_once('=') else {
+                expanded.push(revision.clone());
+                continue;
+            };
+            let selected = format!("{axis}_{value}");
+            let matching: Vec<_> = self
+                .revisions
+                .iter()
+                .flatten()
+                .filter(|known| known.split("__").any(|a| *a == selected))
+                .cloned()
+                .collect();
+            if matching.is_empty() {
+                let msg = match self.revisions {
+                    // Selectors are resolved while parsing, so the matrix must come first.
+                    None => format!(
+                        "`{revision}` selects revisions of a revision matrix, \
+                        but no `{}@revisions-matrix` was declared above it",
+                        self.comment_start
+                    ),
+                    Some(_) => {
+                        format!("the revision matrix has no axis `{axis}` with the value `{value}`")
+                    }
+                };
+                self.error(span.clone(), msg);
+            }
+            expanded.extend(matching);
+        }
+        // Don't apply the commands of unresolved selectors to all revisions.
+        if expanded.is_empty() && !revisions.is_empty() {
+            return;
+        }
+        let mut seen = HashSet::new();
+        expanded.retain(|revision| seen.insert(revision.clone()));
+        let revisions = expanded;
         let mut this = CommentParser {
             comment_start: self.comment_start,
+            includes: vec![],
+            comparisons: std::mem::take(&mut self.comparisons),
+            output_kinds: std::mem::take(&mut self.output_kinds),
             errors: std::mem::take(&mut self.errors),
             commands: std::mem::take(&mut self.commands),
             comments: self
@@ -670,9 +1089,15 @@ impl CommentParser<Comments> {
         };
         f(&mut this);
         let CommentParser {
-            errors, commands, ..
+            errors,
+            commands,
+            comparisons,
+            output_kinds,
+            ..
         } = this;
         self.commands = commands;
+        self.comparisons = comparisons;
+        self.output_kinds = output_kinds;
         self.errors = errors;
     }
 }
@@ -681,7 +1106,7 @@ impl CommentParser<&mut Revisioned> {
     fn parse_normalize_test(
         &mut self,
         args: Spanned<&str>,
-        mode: &str,
+        command: &str,
     ) -> Option<(Regex, Vec<u8>)> {
         let (from, rest) = self.parse_str(args);
 
@@ -690,9 +1115,7 @@ impl CommentParser<&mut Revisioned> {
             None => {
                 self.error(
                     rest.span(),
-                    format!(
-                        "normalize-{mode}-test needs a pattern and replacement separated by `->`"
-                    ),
+                    format!("`{command}` needs a pattern and replacement separated by `->`"),
                 );
                 return None;
             }
@@ -752,14 +1175,43 @@ impl CommentParser<Comments> {
                     }
                 }
             }
-            "normalize-stderr-test" => (this, args, _span){
-                if let Some((regex, replacement)) = this.parse_normalize_test(args, "stderr") {
-                    this.normalize_stderr.push((regex.into(), replacement))
+            "normalize-stderr-test" => (this, args, span){
+                if let Some((regex, replacement)) = this.parse_normalize_test(args, "normalize-stderr-test") {
+                    this.push_normalization("stderr", regex.into(), replacement, span)
+                }
+            }
+            "normalize-stdout-test" => (this, args, span){
+                if let Some((regex, replacement)) = this.parse_normalize_test(args, "normalize-stdout-test") {
+                    this.push_normalization("stdout", regex.into(), replacement, span)
+                }
+            }
+            "normalize" => (this, args, span){
+                for name in args.split_whitespace() {
+                    match Match::builtin(name) {
+                        Some((filter, replacement)) => {
+                            this.push_normalization("stderr", filter.clone(), replacement.to_vec(), span.clone());
+                            this.push_normalization("stdout", filter, replacement.to_vec(), span.clone());
+                        }
+                        None => this.error(
+                            args.span(),
+                            format!(
+                                "`{name}` is not a built-in normalization, expected one of {}",
+                                BUILTIN_FILTERS.iter().map(|name| format!("`{name}`")).collect::<Vec<_>>().join(", "),
+                            ),
+                        ),
+                    }
+                }
+            }
+            "min-rustc-version" => (this, args, span){
+                match parse_rustc_version(*args) {
+                    Ok(version) => this.push_only(Condition::MinRustcVersion(version), span),
+                    Err(msg) => this.error(args.span(), msg),
                 }
             }
-            "normalize-stdout-test" => (this, args, _span){
-                if let Some((regex, replacement)) = this.parse_normalize_test(args, "stdout") {
-                    this.normalize_stdout.push((regex.into(), replacement))
+            "max-rustc-version" => (this, args, span){
+                match parse_rustc_version(*args) {
+                    Ok(version) => this.push_only(Condition::MaxRustcVersion(version), span),
+                    Err(msg) => this.error(args.span(), msg),
                 }
             }
             "error-pattern" => (this, _args, span){
@@ -779,6 +1231,15 @@ impl CommentParser<Comments> {
                 );
                 this.stderr_per_bitwidth = true;
             }
+            "shared-snapshot" => (this, _args, span){
+                // args are ignored (can be used as comment)
+                this.check(
+                    span,
+                    !this.shared_snapshot,
+                    "cannot specify `shared-snapshot` twice",
+                );
+                this.shared_snapshot = true;
+            }
             "run-rustfix" => (this, _args, span){
                 this.error(span, "rustfix is now ran by default when applicable suggestions are found");
             }
@@ -819,28 +1280,84 @@ impl CommentParser<&mut Revisioned> {
             match Condition::parse(*rest, *args) {
                 Ok(cond) => {
                     if command.starts_with("ignore") {
-                        self.ignore.push(cond)
+                        self.ignore.push(cond);
+                        self.ignore_spans.push(command.span());
                     } else {
-                        self.only.push(cond)
+                        self.push_only(cond, command.span())
                     }
                 }
                 Err(msg) => self.error(rest.span(), msg),
             }
+        } else if let Some(kind) = command
+            .strip_prefix("normalize-")
+            .and_then(|kind| self.output_kind(&kind))
+        {
+            if let Some((regex, replacement)) = self.parse_normalize_test(args, &command) {
+                self.push_normalization(&kind, regex.into(), replacement, command.span())
+            }
+        } else if let Some(kind) = command
+            .strip_prefix("compare-")
+            .and_then(|kind| self.output_kind(&kind))
+        {
+            let name = args.trim();
+            if let Some(&comparison) = self.comparisons.get(*name) {
+                if let Some(prev) = self
+                    .compare
+                    .insert(kind, Spanned::new(comparison, command.span()))
+                {
+                    self.error(prev.span(), format!("`{}` specified twice", *command));
+                }
+            } else {
+                let known: Vec<_> = self.comparisons.keys().collect();
+                self.error(
+                    name.span(),
+                    format!("unknown comparison `{}`, expected one of {known:?}", *name),
+                );
+            }
         } else {
-            let best_match = self
-                .commands
-                .keys()
-                .min_by_key(|key| levenshtein::levenshtein(key, *command))
-                .unwrap();
-            self.error(
-                command.span(),
-                format!(
-                    "`{}` is not a command known to `ui_test`, did you mean `{best_match}`?",
-                    *command
-                ),
-            );
+            let mut msg = format!("`{}` is not a command known to `ui_test`", *command);
+            if let Some(best_match) = self.suggest_command(&command) {
+                msg += &format!(", did you mean `{best_match}`?");
+            }
+            self.error(command.span(), msg);
         }
     }
+
+    /// Turns the `run-stdout` of `//@normalize-run-stdout` into the output kind `run.stdout`,
+    /// if tests can produce outputs of that kind.
+    fn output_kind(&self, kind: &str) -> Option<String> {
+        let kind = kind.replace('-', ".");
+        let numbered_fixed = kind
+            .strip_suffix(".fixed")
+            .is_some_and(|n| n.parse::<usize>().is_ok());
+        (numbered_fixed || self.output_kinds.contains(&&*kind)).then_some(kind)
+    }
+
+    /// Finds the known command closest to `command`, if it is close enough to be a typo.
+    /// Considers the built-in commands, [`Config::custom_comments`],
+    /// `ignore-`/`only-` conditions and the `normalize-`/`compare-` commands for all
+    /// [`Config::output_kinds`].
+    fn suggest_command(&self, command: &str) -> Option<String> {
+        let conditions = command
+            .split_once('-')
+            .map(|(_, condition)| ["ignore", "only"].map(|prefix| format!("{prefix}-{condition}")))
+            .into_iter()
+            .flatten();
+        let output_kinds = self.output_kinds.iter().flat_map(|kind| {
+            let kind = kind.replace('.', "-");
+            [format!("normalize-{kind}"), format!("compare-{kind}")]
+        });
+        let (distance, best_match) = self
+            .commands
+            .keys()
+            .chain(&["revisions", "revisions-matrix", "include"])
+            .map(|&key| key.to_owned())
+            .chain(conditions)
+            .chain(output_kinds)
+            .map(|key| (levenshtein::levenshtein(&key, command), key))
+            .min()?;
+        (distance <= (command.len() / 3).max(2)).then_some(best_match)
+    }
 }
 
 impl<CommentsType> CommentParser<CommentsType> {
@@ -923,13 +1440,42 @@ impl<CommentsType> CommentParser<CommentsType> {
             ),
         }
     }
+
+    // parse something like \[x[0-9]+\]
+    fn parse_count<'a>(
+        &mut self,
+        pattern: Spanned<&'a str>,
+    ) -> std::result::Result<(Option<NonZeroUsize>, Spanned<&'a str>), ()> {
+        let Some(s) = pattern.strip_prefix("[x") else {
+            return Ok((None, pattern));
+        };
+        let Some(end) = s.find(']') else {
+            self.error(s.span(), "`[x` without corresponding `]`");
+            return Err(());
+        };
+        let (count, rest) = s.split_at(end);
+        match count.content.parse::<NonZeroUsize>() {
+            Ok(n) => Ok((Some(n), rest.split_at(1).1)),
+            Err(_) => {
+                self.error(
+                    count.span(),
+                    format!(
+                        "`{}` is not a valid count, expected `[xN]` with N > 0",
+                        *count
+                    ),
+                );
+                Err(())
+            }
+        }
+    }
 }
 
 impl CommentParser<&mut Revisioned> {
     // parse something like:
     // (\[[a-z]+(,[a-z]+)*\])?
-    // (?P<offset>\||[\^]+)? *
-    // ((?P<level>ERROR|HELP|WARN|NOTE): (?P<text>.*))|(?P<code>[a-z0-9_:]+)
+    // (?P<offset>\||\?|[\^]+)? *
+    // ((?P<level>ERROR|HELP|WARN|NOTE)(\[x(?P<count>[0-9]+)\])?: (?P<text>.*))|
+    // ((?P<level>ERROR|HELP|WARN|NOTE) +)?((?P<code>[a-z0-9_:]+)(\[x(?P<count>[0-9]+)\])?)
     fn parse_pattern(
         &mut self,
         pattern: Spanned<&str>,
@@ -938,8 +1484,16 @@ impl CommentParser<&mut Revisioned> {
     ) -> ParsePatternResult {
         let c = pattern.chars().next();
         let mut res = ParsePatternResult::Other;
+        let mut anywhere = false;
 
         let (match_line, pattern) = match c {
+            Some(Spanned {
+                content: '?',
+                span: _,
+            }) => {
+                anywhere = true;
+                (current_line, pattern.split_at(1).1)
+            }
             Some(Spanned { content: '|', span }) => (
                 match fallthrough_to {
                     Some(match_line) => {
@@ -1031,7 +1585,19 @@ impl CommentParser<&mut Revisioned> {
             .unwrap_or(pattern.len());
 
         let (level_or_code, pattern) = pattern.split_at(offset);
-        if let Some(level) = level_or_code.strip_suffix(":") {
+        let (count, pattern) = match self.parse_count(pattern) {
+            Ok(parsed) => parsed,
+            Err(()) => return res,
+        };
+        // With a count, the `:` comes after the count, as in `ERROR[x2]: msg`.
+        let (level, pattern) = match level_or_code.strip_suffix(":") {
+            Some(level) => (Some(level), pattern),
+            None => match pattern.strip_prefix(":") {
+                Some(pattern) if count.is_some() => (Some(level_or_code.clone()), pattern),
+                _ => (None, pattern),
+            },
+        };
+        if let Some(level) = level {
             let level = match (*level).parse() {
                 Ok(level) => level,
                 Err(msg) => {
@@ -1049,11 +1615,40 @@ impl CommentParser<&mut Revisioned> {
             self.error_matches.push(ErrorMatch {
                 kind: ErrorMatchKind::Pattern { pattern, level },
                 line: match_line,
+                count,
+                anywhere,
+            });
+        } else if let Ok(level) = (*level_or_code).parse::<Level>() {
+            // A level followed by a diagnostic code, as in `//~ WARN unused_variables`.
+            // Level names shouldn't conflict with any real diagnostic code.
+            let code = pattern.trim_start();
+            let offset = code
+                .bytes()
+                .position(|c| !(c.is_ascii_alphanumeric() || c == b'_' || c == b':'))
+                .unwrap_or(code.len());
+            if offset == 0 || count.is_some() {
+                self.error(level_or_code.span(), "no `:` after level found");
+                return res;
+            }
+            let (code, pattern) = code.split_at(offset);
+            let (count, pattern) = match self.parse_count(pattern) {
+                Ok(parsed) => parsed,
+                Err(()) => return res,
+            };
+            // More than one word after the level is most likely a message missing its `:`.
+            if !pattern.trim_start().is_empty() {
+                self.error(level_or_code.span(), "no `:` after level found");
+                return res;
+            }
+            self.error_matches.push(ErrorMatch {
+                kind: ErrorMatchKind::Code {
+                    code: Spanned::new(code.to_string(), code.span()),
+                    level,
+                },
+                line: match_line,
+                count,
+                anywhere,
             });
-        } else if (*level_or_code).parse::<Level>().is_ok() {
-            // Shouldn't conflict with any real diagnostic code
-            self.error(level_or_code.span(), "no `:` after level found");
-            return res;
         } else if !pattern.trim_start().is_empty() {
             self.error(
                 pattern.span(),
@@ -1062,11 +1657,13 @@ impl CommentParser<&mut Revisioned> {
             return res;
         } else {
             self.error_matches.push(ErrorMatch {
-                kind: ErrorMatchKind::Code(Spanned::new(
-                    level_or_code.to_string(),
-                    level_or_code.span(),
-                )),
+                kind: ErrorMatchKind::Code {
+                    code: Spanned::new(level_or_code.to_string(), level_or_code.span()),
+                    level: Level::Error,
+                },
                 line: match_line,
+                count,
+                anywhere,
             });
         };
 
@@ -1,6 +1,9 @@
 use super::Comments;
 use crate::{
+    diagnostics::Level,
+    filter::Match,
     parser::{Condition, ErrorMatchKind, Pattern},
+    tests::TempDir,
     Config, Error,
 };
 use spanned::{Span, Spanned};
@@ -82,13 +85,139 @@ fn main() {
     println!("parsed comments: {:#?}", comments);
     assert_eq!(comments.revisioned.len(), 1);
     let revisioned = &comments.revisioned[&vec![]];
-    let ErrorMatchKind::Code(code) = &revisioned.error_matches[0].kind else {
+    let ErrorMatchKind::Code { code, .. } = &revisioned.error_matches[0].kind else {
         panic!("expected diagnostic code matcher");
     };
     assert_eq!(line!(&code.span, s), 3);
     assert_eq!(**code, "E0308");
 }
 
+#[test]
+fn parse_leveled_code_comment() {
+    let s = r"
+fn main() {
+    let x = 0; //~ WARN unused_variables[x2]
+}
+    ";
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&vec![]];
+    let ErrorMatchKind::Code { code, level } = &revisioned.error_matches[0].kind else {
+        panic!("expected diagnostic code matcher");
+    };
+    assert_eq!(line!(&code.span, s), 3);
+    assert_eq!(**code, "unused_variables");
+    assert_eq!(*level, Level::Warn);
+    assert_eq!(revisioned.error_matches[0].count.unwrap().get(), 2);
+}
+
+#[test]
+fn parse_counted_comment() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32; //~ ERROR[x3]: mismatched types
+    //~^ E0308[x2]
+}
+    ";
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&vec![]];
+    let ErrorMatchKind::Pattern { pattern, .. } = &revisioned.error_matches[0].kind else {
+        panic!("expected pattern matcher");
+    };
+    assert_eq!(
+        format!("{:?}", **pattern),
+        r#"SubString("mismatched types")"#
+    );
+    assert_eq!(revisioned.error_matches[0].count.unwrap().get(), 3);
+    let ErrorMatchKind::Code { code, .. } = &revisioned.error_matches[1].kind else {
+        panic!("expected diagnostic code matcher");
+    };
+    assert_eq!(**code, "E0308");
+    assert_eq!(revisioned.error_matches[1].line.get(), 3);
+    assert_eq!(revisioned.error_matches[1].count.unwrap().get(), 2);
+}
+
+#[test]
+fn parse_anywhere_comment() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32;
+}
+//~? ERROR: mismatched types
+    ";
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&vec![]];
+    let ErrorMatchKind::Pattern { pattern, .. } = &revisioned.error_matches[0].kind else {
+        panic!("expected pattern matcher");
+    };
+    assert_eq!(
+        format!("{:?}", **pattern),
+        r#"SubString("mismatched types")"#
+    );
+    assert!(revisioned.error_matches[0].anywhere);
+    assert_eq!(revisioned.error_matches[0].line.get(), 5);
+}
+
+#[test]
+fn parse_invalid_count() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32; //~ ERROR[x0]: mismatched types
+}
+    ";
+    let errors = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap_err();
+    println!("parsed comments: {:#?}", errors);
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, span } if line!(span, s) == 3 => {
+            assert_eq!(msg, "`0` is not a valid count, expected `[xN]` with N > 0")
+        }
+        _ => unreachable!(),
+    }
+}
+
 #[test]
 fn parse_missing_level() {
     let s = r"
@@ -303,3 +432,411 @@ fn parse_invalid_filter() {
     )
     .unwrap_err();
 }
+
+#[test]
+fn parse_cfg_condition() {
+    let s = r#"//@ignore-cfg: all(unix, not(target_os = "linux"))"#;
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&vec![]];
+    assert_eq!(revisioned.ignore.len(), 1);
+    match &revisioned.ignore[0] {
+        Condition::Cfg(expr) => {
+            assert_eq!(expr.to_string(), r#"all(unix, not(target_os = "linux"))"#)
+        }
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_invalid_cfg_condition() {
+    let s = r"//@only-cfg: any(unix";
+    Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn parse_version_conditions() {
+    let s = r"//@min-rustc-version: 1.80
+//@max-rustc-version: 1.82.1
+//@ignore-stable";
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    let revisioned = &comments.revisioned[&vec![]];
+    assert_eq!(revisioned.only.len(), 2);
+    match &revisioned.only[0] {
+        Condition::MinRustcVersion(version) => assert_eq!(version.to_string(), "1.80.0"),
+        _ => unreachable!(),
+    }
+    match &revisioned.only[1] {
+        Condition::MaxRustcVersion(version) => assert_eq!(version.to_string(), "1.82.1"),
+        _ => unreachable!(),
+    }
+    match &revisioned.ignore[..] {
+        [Condition::Channel(rustc_version::Channel::Stable)] => {}
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn parse_invalid_version_condition() {
+    let s = r"//@min-rustc-version: 1.80-nightly";
+    Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap_err();
+}
+
+#[test]
+fn parse_revisions_matrix() {
+    let s = r"//@revisions-matrix: edition=[2018,2021] x opt=[O0, O3]
+//@[edition=2021] compile-flags: --edition=2021
+//@[edition_2018__opt_O3] compile-flags: -O";
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    println!("parsed comments: {:#?}", comments);
+    assert_eq!(
+        comments.revisions.as_deref().unwrap(),
+        [
+            "edition_2018__opt_O0",
+            "edition_2018__opt_O3",
+            "edition_2021__opt_O0",
+            "edition_2021__opt_O3"
+        ]
+    );
+    let flags = |revision| {
+        comments
+            .for_revision(revision)
+            .flat_map(|r| r.compile_flags.iter().cloned())
+            .collect::<Vec<_>>()
+    };
+    assert!(flags("edition_2018__opt_O0").is_empty());
+    assert_eq!(flags("edition_2018__opt_O3"), ["-O"]);
+    assert_eq!(flags("edition_2021__opt_O0"), ["--edition=2021"]);
+    assert_eq!(flags("edition_2021__opt_O3"), ["--edition=2021"]);
+}
+
+#[test]
+fn parse_invalid_revisions_matrix() {
+    let errors = |s: &str| {
+        let errors = Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: PathBuf::new(),
+                    bytes: 0..s.len(),
+                },
+            ),
+            &Config::dummy(),
+        )
+        .unwrap_err();
+        errors
+            .into_iter()
+            .map(|error| match error {
+                Error::InvalidComment { msg, .. } => msg,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>()
+    };
+    errors("//@revisions-matrix: edition=2018,2021");
+    assert_eq!(
+        errors("//@revisions-matrix: edition=[2018,2021,2018]"),
+        ["revision matrix axis `edition` has the value `2018` twice"]
+    );
+    assert_eq!(
+        errors("//@[edition=2021] compile-flags: -O\n//@revisions-matrix: edition=[2018,2021]"),
+        ["`edition=2021` selects revisions of a revision matrix, but no `//@revisions-matrix` was declared above it"]
+    );
+    assert_eq!(
+        errors("//@revisions-matrix: edition=[2018,2021]\n//@[edtion=2021] compile-flags: -O"),
+        ["the revision matrix has no axis `edtion` with the value `2021`"]
+    );
+}
+
+#[test]
+fn parse_include() {
+    let dir = TempDir::new("parse_include");
+    std::fs::create_dir_all(dir.join("common")).unwrap();
+    std::fs::write(
+        dir.join("common/normalize-paths.ui"),
+        "Normalize paths for all tests\n//@normalize-stderr-test: \"\\\\\" -> \"/\"\n//@compile-flags: --edition=2021\n",
+    )
+    .unwrap();
+    std::fs::write(dir.join("common/cycle-a.ui"), "//@include: cycle-b.ui\n").unwrap();
+    std::fs::write(dir.join("common/cycle-b.ui"), "//@include: cycle-a.ui\n").unwrap();
+    std::fs::write(dir.join("common/typo.ui"), "\n//@compile-flag: -O\n").unwrap();
+    std::fs::write(
+        dir.join("common/indented.ui"),
+        "//@check-pass\n    //@compile-flags: -O\n",
+    )
+    .unwrap();
+    std::fs::create_dir_all(dir.join("tests")).unwrap();
+    let parse = |s: &str| {
+        Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: dir.join("tests/foo.rs"),
+                    bytes: 0..s.len(),
+                },
+            ),
+            &Config::dummy(),
+        )
+    };
+
+    let comments = parse("//@include: ../common/normalize-paths.ui\nfn main() {}").unwrap();
+    assert_eq!(comments.base_immut().compile_flags, ["--edition=2021"]);
+    assert_eq!(comments.base_immut().normalize_stderr.len(), 1);
+
+    let errors = parse("//@include: ../common/cycle-a.ui").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, span } => {
+            assert!(msg.starts_with("include cycle detected"), "{msg}");
+            assert_eq!(
+                span.file.canonicalize().unwrap(),
+                dir.join("common/cycle-b.ui").canonicalize().unwrap()
+            );
+        }
+        _ => unreachable!(),
+    }
+
+    let errors = parse("//@include: ../common/typo.ui").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, span } => {
+            assert!(msg.contains("`compile-flag` is not a command"), "{msg}");
+            assert_eq!(
+                span.file.canonicalize().unwrap(),
+                dir.join("common/typo.ui").canonicalize().unwrap()
+            );
+        }
+        _ => unreachable!(),
+    }
+
+    let errors = parse("//@include: ../common/indented.ui").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    match &errors[0] {
+        Error::InvalidComment { msg, span } => {
+            assert!(msg.contains("must be at the start of the line"), "{msg}");
+            assert_eq!(span.bytes.start, "//@check-pass\n    //".len());
+        }
+        _ => unreachable!(),
+    }
+
+    parse("//@include: ../common/missing.ui").unwrap_err();
+}
+
+#[test]
+fn suggest_misspelled_commands() {
+    let errors = |s: &str| {
+        Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: PathBuf::new(),
+                    bytes: 0..s.len(),
+                },
+            ),
+            &Config::rustc(""),
+        )
+        .unwrap_err()
+        .into_iter()
+        .map(|error| match error {
+            Error::InvalidComment { msg, .. } => msg,
+            _ => unreachable!(),
+        })
+        .collect::<Vec<_>>()
+    };
+    let suggestion = |s: &str| {
+        let errors = errors(s);
+        assert_eq!(errors.len(), 1, "{errors:?}");
+        errors[0]
+            .split_once("did you mean `")
+            .map(|(_, rest)| rest.split('`').next().unwrap().to_owned())
+    };
+    assert_eq!(suggestion("//@edtion: 2021").as_deref(), Some("edition"));
+    assert_eq!(
+        suggestion("//@compile-flag: -O").as_deref(),
+        Some("compile-flags")
+    );
+    assert_eq!(suggestion("//@revison: a b").as_deref(), Some("revisions"));
+    assert_eq!(
+        suggestion("//@ignroe-windows").as_deref(),
+        Some("ignore-windows")
+    );
+    // custom comments
+    assert_eq!(suggestion("//@no-rustfx").as_deref(), Some("no-rustfix"));
+    assert_eq!(suggestion("//@xyzzy"), None);
+    assert_eq!(
+        suggestion(r#"//@normalize-stdrr-test: "a" -> "b""#).as_deref(),
+        Some("normalize-stderr-test")
+    );
+    assert_eq!(
+        suggestion(r#"//@normalize-run-stodut: "a" -> "b""#).as_deref(),
+        Some("normalize-run-stdout")
+    );
+    assert_eq!(
+        suggestion("//@compare-stdrr: unordered").as_deref(),
+        Some("compare-stderr")
+    );
+
+    assert_eq!(suggestion("//~~ ERROR: foo").as_deref(), Some("//~"));
+    assert_eq!(suggestion("//@@check-pass").as_deref(), Some("//@"));
+    assert!(errors("//   @check-pass")[0].contains("looks suspiciously like a test suite command"));
+}
+
+#[test]
+fn parse_builtin_normalize() {
+    let parse = |s: &str| {
+        Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: PathBuf::new(),
+                    bytes: 0..s.len(),
+                },
+            ),
+            &Config::dummy(),
+        )
+    };
+    let comments = parse("//@normalize: sysroot std-line-numbers").unwrap();
+    assert_eq!(comments.base_immut().normalize_stderr.len(), 2);
+    assert_eq!(comments.base_immut().normalize_stdout.len(), 2);
+    assert!(matches!(
+        comments.base_immut().normalize_stderr[1].0,
+        Match::StdLineNumbers
+    ));
+
+    let errors = parse("//@normalize: sysroot sysrot").unwrap_err();
+    assert_eq!(errors.len(), 1);
+}
+
+#[test]
+fn parse_normalize_output_kinds() {
+    let s = r#"//@normalize-stderr-test: "a" -> "b"
+//@normalize-fixed: "c" -> "d"
+//@normalize-run-stdout: "e" -> "f"
+"#;
+    let comments = Comments::parse(
+        Spanned::new(
+            s.as_bytes(),
+            Span {
+                file: PathBuf::new(),
+                bytes: 0..s.len(),
+            },
+        ),
+        &Config::dummy(),
+    )
+    .unwrap();
+    let replacements = |kind| {
+        comments
+            .base_immut()
+            .normalizations(kind)
+            .map(|(_, replacement)| String::from_utf8(replacement.clone()).unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(replacements("stderr"), ["b"]);
+    assert_eq!(replacements("run.stderr"), ["b"]);
+    assert_eq!(replacements("rev.64bit.stderr"), ["b"]);
+    assert!(replacements("stdout").is_empty());
+    assert_eq!(replacements("run.stdout"), ["f"]);
+    assert_eq!(replacements("fixed"), ["d"]);
+    assert_eq!(replacements("2.fixed"), ["d"]);
+    assert!(replacements("notfixed").is_empty());
+
+    let parse = |s: &str, config: &Config| {
+        Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: PathBuf::new(),
+                    bytes: 0..s.len(),
+                },
+            ),
+            config,
+        )
+    };
+    let mut config = Config::dummy();
+    assert!(parse(r#"//@normalize-2-fixed: "a" -> "b""#, &config).is_ok());
+    // Typos don't silently create new output kinds.
+    assert!(parse(r#"//@normalize-stdrr: "a" -> "b""#, &config).is_err());
+    assert!(parse(r#"//@normalize-custom: "a" -> "b""#, &config).is_err());
+    config.output_kinds.push("custom");
+    assert!(parse(r#"//@normalize-custom: "a" -> "b""#, &config).is_ok());
+}
+
+#[test]
+fn parse_compare() {
+    let parse = |s: &str| {
+        Comments::parse(
+            Spanned::new(
+                s.as_bytes(),
+                Span {
+                    file: PathBuf::new(),
+                    bytes: 0..s.len(),
+                },
+            ),
+            &Config::dummy(),
+        )
+    };
+    let comments = parse("//@compare-stderr: unordered\n//@compare-run-stdout: subset\n").unwrap();
+    let compare = &comments.base_immut().compare;
+    assert_eq!(compare.keys().collect::<Vec<_>>(), ["run.stdout", "stderr"]);
+    assert!(compare["stderr"](b"b\na\n", b"a\nb\n"));
+    assert!(compare["run.stdout"](b"a\nb\n", b"b\n"));
+
+    let errors = parse("//@compare-stderr: sorted").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    // Misspelled output kinds are unknown commands, not new kinds.
+    let errors = parse("//@compare-stdrr: unordered").unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(parse("//@compare-1-fixed: unordered").is_ok());
+    let errors = parse("//@compare-stderr: exact\n//@compare-stderr: unordered").unwrap_err();
+    assert_eq!(errors.len(), 1);
+}
@@ -5,19 +5,21 @@
 //! some boolean settings have no way to disable them.
 
 use crate::build_manager::BuildManager;
+use crate::compare::{self, Comparison};
 use crate::custom_flags::Flag;
 pub use crate::diagnostics::Level;
 use crate::diagnostics::{Diagnostics, Message};
-pub use crate::parser::{Comments, Condition, Revisioned};
-use crate::parser::{ErrorMatch, ErrorMatchKind, OptWithLine};
+use crate::filter::RuleApplication;
+use crate::parser::{output_kind_applies, ErrorMatch, ErrorMatchKind, OptWithLine, Pattern};
+pub use crate::parser::{Comments, Condition, Normalizations, Revisioned};
 use crate::status_emitter::{SilentStatus, TestStatus};
 use crate::test_result::{Errored, TestOk, TestResult};
-use crate::{core::strip_path_prefix, Config, Error, Errors};
+use crate::{core::strip_path_prefix, display, Config, Error, Errors, ExpectedLine};
 use spanned::Spanned;
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::num::NonZeroUsize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, Output};
 use std::sync::Arc;
 
@@ -154,7 +156,10 @@ impl TestConfig {
     }
 
     pub(crate) fn output_path(&self, kind: &str) -> PathBuf {
-        let ext = self.extension(kind);
+        self.output_path_with_extension(self.extension(kind))
+    }
+
+    fn output_path_with_extension(&self, ext: String) -> PathBuf {
         if self.comments().any(|r| r.stderr_per_bitwidth) {
             return self
                 .status
@@ -165,19 +170,60 @@ impl TestConfig {
     }
 
     pub(crate) fn normalize(&self, text: &[u8], kind: &str) -> Vec<u8> {
+        self.normalize_inner(text, kind, None)
+    }
+
+    /// Like [`Self::normalize`], but also returns how often each rule replaced something.
+    pub(crate) fn normalize_traced(
+        &self,
+        text: &[u8],
+        kind: &str,
+    ) -> (Vec<u8>, Vec<RuleApplication>) {
+        let mut trace = vec![];
+        let text = self.normalize_inner(text, kind, Some(&mut trace));
+        (text, trace)
+    }
+
+    fn normalize_inner(
+        &self,
+        text: &[u8],
+        kind: &str,
+        mut trace: Option<&mut Vec<RuleApplication>>,
+    ) -> Vec<u8> {
         let mut text = text.to_owned();
 
-        for (from, to) in self.comments().flat_map(|r| match kind {
-            _ if kind.ends_with("fixed") => &[] as &[_],
-            "stderr" => &r.normalize_stderr,
-            "stdout" => &r.normalize_stdout,
-            _ => unreachable!(),
-        }) {
-            text = from.replace_all(&text, to).into_owned();
+        for (from, to) in self.comments().flat_map(|r| r.normalizations(kind)) {
+            let (replaced, replacements) = from.replace_all(&text, to, self.status.path());
+            text = replaced.into_owned();
+            if let Some(trace) = &mut trace {
+                let rule = RuleApplication::describe(from, to);
+                let span = self
+                    .comments()
+                    .flat_map(|r| &r.normalization_spans)
+                    .find(|(declared, _)| *declared == rule)
+                    .map(|(_, span)| span.clone())
+                    .unwrap_or_default();
+                trace.push(RuleApplication {
+                    span,
+                    rule,
+                    replacements,
+                });
+            }
         }
         text
     }
 
+    /// How outputs of the given kind are compared to their output files, see
+    /// [`Revisioned::compare`]. The most specific kind wins, and among equally specific
+    /// kinds revisions override the defaults. Defaults to [`compare::exact`].
+    pub fn comparison(&self, kind: &str) -> Comparison {
+        self.comments()
+            .flat_map(|r| &r.compare)
+            .filter(|(key, _)| output_kind_applies(key, kind))
+            .max_by_key(|(key, _)| key.len())
+            .map_or(compare::exact, |(_, comparison)| **comparison)
+    }
+
     pub(crate) fn check_test_output(&self, errors: &mut Errors, stdout: &[u8], stderr: &[u8]) {
         // Check output files (if any)
         // Check output files against actual output
@@ -185,10 +231,65 @@ impl TestConfig {
         self.check_output(stdout, errors, "stdout");
     }
 
-    pub(crate) fn check_output(&self, output: &[u8], errors: &mut Errors, kind: &str) -> PathBuf {
+    /// The output kind of an output file, e.g. `stderr` for `foo.stderr` or `run.stdout` for
+    /// `foo.rev.run.stdout`, used to pick the normalizations to apply.
+    /// Revisions and bitwidths are kept, as normalizations for a kind also apply to
+    /// all kinds ending in `.{kind}`.
+    pub fn output_kind(&self, path: &Path) -> String {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let stem = self.status.path().file_stem().unwrap_or_default();
+        name.strip_prefix(&*stem.to_string_lossy())
+            .and_then(|kind| kind.strip_prefix('.'))
+            .unwrap_or(&name)
+            .to_owned()
+    }
+
+    /// Compare an output of the test against the file for the given output kind
+    /// (e.g. `stderr` or `1.fixed`), applying the normalizations for that kind.
+    /// Custom flags can use this to check additional outputs.
+    /// Returns the path of the output file.
+    pub fn check_output(&self, output: &[u8], errors: &mut Errors, kind: &str) -> PathBuf {
         let path = self.output_path(kind);
-        (self.config.output_conflict_handling)(&path, output, errors, self);
-        path
+        let revision = self.status.revision();
+        if revision.is_empty() || !self.comments().any(|r| r.shared_snapshot) {
+            (self.config.output_conflict_handling)(&path, output, errors, self);
+            return path;
+        }
+
+        // The first revision that isn't ignored owns the shared file, all other revisions
+        // only get their own file if their output differs from it. Revisions are run in order,
+        // so the shared file is already up to date when blessing.
+        let shared = self.output_path_with_extension(kind.to_string());
+        let Some(owner) = self
+            .comments
+            .revisions
+            .iter()
+            .flatten()
+            .find(|revision| self.config.test_file_conditions(&self.comments, revision))
+        else {
+            errors.push(Error::ConfigError(format!(
+                "`shared-snapshot` found no revision of `{}` that is run",
+                display(self.status.path())
+            )));
+            (self.config.output_conflict_handling)(&path, output, errors, self);
+            return path;
+        };
+        let shares = owner == revision || {
+            let kind = self.output_kind(&shared);
+            self.comparison(&kind)(
+                &self.normalize(output, &kind),
+                &std::fs::read(&shared).unwrap_or_default(),
+            )
+        };
+        if shares {
+            (self.config.output_conflict_handling)(&shared, output, errors, self);
+            // A separate file for this revision is redundant.
+            (self.config.output_conflict_handling)(&path, &[], errors, self);
+            shared
+        } else {
+            (self.config.output_conflict_handling)(&path, output, errors, self);
+            path
+        }
     }
 
     /// Read diagnostics from a test's output.
@@ -203,11 +304,16 @@ impl TestConfig {
         let diagnostics = self.process(&output.stderr);
         self.check_test_output(&mut errors, &output.stdout, &diagnostics.rendered);
         // Check error annotations in the source against output
+        let mut annotation_errors = vec![];
         self.check_annotations(
             diagnostics.messages,
             diagnostics.messages_from_unknown_file_or_line,
-            &mut errors,
+            &mut annotation_errors,
         )?;
+        if self.config.bless_annotations {
+            self.bless_annotations(&mut annotation_errors);
+        }
+        errors.extend(annotation_errors);
         if errors.is_empty() {
             Ok(output)
         } else {
@@ -226,6 +332,9 @@ impl TestConfig {
         mut messages_from_unknown_file_or_line: Vec<Message>,
         errors: &mut Errors,
     ) -> Result<(), Errored> {
+        // Unmatched patterns and their level, to look for near misses once all annotations
+        // have claimed their messages.
+        let mut unmatched_patterns = vec![];
         let error_patterns = self.comments().flat_map(|r| r.error_in_other_files.iter());
 
         let mut seen_error_match = None;
@@ -242,7 +351,8 @@ impl TestConfig {
             } else {
                 errors.push(Error::PatternNotFound {
                     pattern: error_pattern.clone(),
-                    expected_line: None,
+                    expected_line: ExpectedLine::OtherFile,
+                    near_misses: vec![],
                 });
             }
         }
@@ -258,68 +368,117 @@ impl TestConfig {
         // We will ensure that *all* diagnostics of level at least `lowest_annotation_level`
         // are matched.
         let mut lowest_annotation_level = Level::Error;
-        'err: for &ErrorMatch { ref kind, line } in
-            self.comments().flat_map(|r| r.error_matches.iter())
+        // `//~?` annotations are resolved last, so they only pick up messages
+        // that no line-anchored annotation claimed.
+        let anchored = self
+            .comments()
+            .flat_map(|r| r.error_matches.iter().filter(|m| !m.anywhere));
+        let anywhere = self
+            .comments()
+            .flat_map(|r| r.error_matches.iter().filter(|m| m.anywhere));
+        for &ErrorMatch {
+            ref kind,
+            line,
+            count,
+            anywhere,
+        } in anchored.chain(anywhere)
         {
-            match kind {
-                ErrorMatchKind::Code(code) => {
-                    seen_error_match = Some(code.span());
-                }
-                &ErrorMatchKind::Pattern { ref pattern, level } => {
-                    seen_error_match = Some(pattern.span());
-                    // If we found a diagnostic with a level annotation, make sure that all
-                    // diagnostics of that level have annotations, even if we don't end up finding a matching diagnostic
-                    // for this pattern.
-                    if lowest_annotation_level > level {
-                        lowest_annotation_level = level;
+            let (span, level) = match kind {
+                ErrorMatchKind::Code { code, level } => (code.span(), *level),
+                ErrorMatchKind::Pattern { pattern, level } => (pattern.span(), *level),
+            };
+            // If we found a diagnostic with a level annotation, make sure that all
+            // diagnostics of that level have annotations, even if we don't end up finding a matching diagnostic
+            // for this pattern.
+            if lowest_annotation_level > level {
+                lowest_annotation_level = level;
+            }
+            seen_error_match = Some(span.clone());
+
+            let matches = |msg: &Message| {
+                msg.level == level
+                    && match kind {
+                        ErrorMatchKind::Pattern { pattern, .. } => pattern.matches(&msg.message),
+                        ErrorMatchKind::Code { code, .. } => msg
+                            .code
+                            .as_ref()
+                            .and_then(|msg_code| msg_code.strip_prefix(&diagnostic_code_prefix))
+                            .is_some_and(|msg_code| msg_code == **code),
                     }
+            };
+
+            let expected_line = if anywhere {
+                ExpectedLine::Anywhere
+            } else {
+                ExpectedLine::Line(line)
+            };
+            let mut candidates = messages
+                .iter_mut()
+                .enumerate()
+                .filter(|&(i, _)| anywhere || i == line.get())
+                .map(|(_, msgs)| msgs);
+
+            if let Some(expected) = count {
+                // An explicit count claims every matching message on the line
+                // (or in the whole file for `//~?`).
+                let actual = candidates
+                    .map(|msgs| {
+                        let before = msgs.len();
+                        msgs.retain(|msg| !matches(msg));
+                        before - msgs.len()
+                    })
+                    .sum();
+                if actual != expected.get() {
+                    errors.push(Error::WrongMatchCount {
+                        span,
+                        expected_line,
+                        expected,
+                        actual,
+                    });
                 }
+                continue;
             }
 
-            if let Some(msgs) = messages.get_mut(line.get()) {
-                match kind {
-                    &ErrorMatchKind::Pattern { ref pattern, level } => {
-                        let found = msgs
-                            .iter()
-                            .position(|msg| pattern.matches(&msg.message) && msg.level == level);
-                        if let Some(found) = found {
-                            msgs.remove(found);
-                            continue;
-                        }
-                    }
-                    ErrorMatchKind::Code(code) => {
-                        for (i, msg) in msgs.iter().enumerate() {
-                            if msg.level != Level::Error {
-                                continue;
-                            }
-                            let Some(msg_code) = &msg.code else { continue };
-                            let Some(msg) = msg_code.strip_prefix(&diagnostic_code_prefix) else {
-                                continue;
-                            };
-                            if msg == **code {
-                                msgs.remove(i);
-                                continue 'err;
-                            }
-                        }
-                    }
+            if candidates.any(|msgs| match msgs.iter().position(matches) {
+                Some(found) => {
+                    msgs.remove(found);
+                    true
                 }
+                None => false,
+            }) {
+                continue;
             }
 
+            if let ErrorMatchKind::Pattern { .. } = kind {
+                unmatched_patterns.push((errors.len(), level));
+            }
             errors.push(match kind {
                 ErrorMatchKind::Pattern { pattern, .. } => Error::PatternNotFound {
                     pattern: pattern.clone(),
-                    expected_line: Some(line),
+                    expected_line,
+                    near_misses: vec![],
                 },
-                ErrorMatchKind::Code(code) => Error::CodeNotFound {
+                ErrorMatchKind::Code { code, .. } => Error::CodeNotFound {
                     code: Spanned::new(
                         format!("{}{}", diagnostic_code_prefix, **code),
                         code.span(),
                     ),
-                    expected_line: Some(line),
+                    expected_line,
                 },
             });
         }
 
+        for (index, level) in unmatched_patterns {
+            if let Error::PatternNotFound {
+                pattern,
+                expected_line,
+                near_misses,
+            } = &mut errors[index]
+            {
+                *near_misses = find_near_misses(pattern, level, *expected_line, &messages);
+            }
+        }
+
         let required_annotation_level = self
             .find_one("`require_annotations_for_level` annotations", |r| {
                 r.require_annotations_for_level.clone()
@@ -416,3 +575,69 @@ impl TestConfig {
             .map(|(k, v)| (k.as_ref(), v.as_ref()))
     }
 }
+
+/// The maximum number of near misses reported for a single unmatched pattern.
+const MAX_NEAR_MISSES: usize = 3;
+
+/// Finds the unmatched diagnostics that an unmatched pattern was most likely meant to match,
+/// ordered by their distance to the expected line, then by message similarity and then by
+/// whether their level is the expected one.
+fn find_near_misses(
+    pattern: &Pattern,
+    level: Level,
+    expected_line: ExpectedLine,
+    messages: &[Vec<Message>],
+) -> Vec<(NonZeroUsize, Level, String)> {
+    let text = match pattern {
+        Pattern::SubString(s) => s.as_str(),
+        Pattern::Regex(r) => r.as_str(),
+    };
+    // Allow roughly one typo every three characters.
+    let max_distance = text.chars().count() / 3;
+    let mut near_misses: Vec<_> = messages
+        .iter()
+        .enumerate()
+        .flat_map(|(line, msgs)| msgs.iter().map(move |msg| (line, msg)))
+        .filter_map(|(line, msg)| {
+            // Lines are one-indexed, so there are never any messages at index 0.
+            let line = NonZeroUsize::new(line)?;
+            let line_distance = match expected_line {
+                ExpectedLine::Line(expected) => expected.get().abs_diff(line.get()),
+                ExpectedLine::Anywhere => 0,
+                ExpectedLine::OtherFile => return None,
+            };
+            let distance = if pattern.matches(&msg.message) {
+                0
+            } else {
+                substring_distance(text, &msg.message)
+            };
+            // Prefer messages of the expected level over equally close ones of another level.
+            let key = (line_distance, distance, msg.level != level);
+            (distance <= max_distance).then(|| (key, (line, msg.level, msg.message.clone())))
+        })
+        .collect();
+    near_misses.sort_by_key(|&(key, _)| key);
+    near_misses
+        .into_iter()
+        .take(MAX_NEAR_MISSES)
+        .map(|(_, near_miss)| near_miss)
+        .collect()
+}
+
+/// The edit distance between `pattern` and the closest equally long substring of `message`.
+fn substring_distance(pattern: &str, message: &str) -> usize {
+    let len = pattern.chars().count();
+    let indices: Vec<usize> = message
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([message.len()])
+        .collect();
+    if indices.len() <= len + 1 {
+        return levenshtein::levenshtein(pattern, message);
+    }
+    indices
+        .windows(len + 1)
+        .map(|window| levenshtein::levenshtein(pattern, &message[window[0]..window[len]]))
+        .min()
+        .unwrap()
+}
@@ -1,4 +1,4 @@
-use crate::{diagnostics::Message, display, Error, Errors};
+use crate::{diagnostics::Message, display, filter::RuleApplication, Error, Errors};
 
 use crate::github_actions;
 use bstr::ByteSlice;
@@ -12,13 +12,23 @@ use std::{
 
 use super::{RevisionStyle, StatusEmitter, Summary, TestStatus};
 fn gha_error(error: &Error, test_path: &str, revision: &str) {
-    let file = Spanned::read_from_file(test_path).unwrap();
+    let file = Spanned::read_from_file(test_path).ok();
     let line = |span: &Span| {
-        let line = file
-            .lines()
-            .position(|line| line.span.bytes.contains(&span.bytes.start))
-            .unwrap();
-        NonZeroUsize::new(line + 1).unwrap()
+        let other_file;
+        let file = if span.file.as_os_str().is_empty() || span.file == Path::new(test_path) {
+            file.as_ref()
+        } else {
+            other_file = Spanned::read_from_file(&span.file).ok();
+            other_file.as_ref()
+        };
+        // Show the error at the top if the file can't be read (anymore).
+        file.and_then(|file| {
+            file.lines()
+                .position(|line| line.span.bytes.contains(&span.bytes.start))
+        })
+        .map_or(NonZeroUsize::MIN, |line| {
+            NonZeroUsize::new(line + 1).unwrap()
+        })
     };
     match error {
         Error::ExitStatus {
@@ -35,14 +45,35 @@ fn gha_error(error: &Error, test_path: &str, revision: &str) {
         Error::Command { kind, status } => {
             github_actions::error(test_path, format!("{kind}{revision} failed with {status}"));
         }
-        Error::PatternNotFound { pattern, .. } => {
-            github_actions::error(test_path, format!("Pattern not found{revision}"))
+        Error::PatternNotFound {
+            pattern,
+            near_misses,
+            ..
+        } => {
+            let mut err = github_actions::error(test_path, format!("Pattern not found{revision}"))
                 .line(line(&pattern.span));
+            for (line, level, msg) in near_misses {
+                let level = level.annotation_name();
+                writeln!(err, "did you mean `{level}: {msg}` on line {line}?").unwrap();
+            }
         }
         Error::CodeNotFound { code, .. } => {
             github_actions::error(test_path, format!("Diagnostic code not found{revision}"))
                 .line(line(&code.span));
         }
+        Error::WrongMatchCount {
+            span,
+            expected,
+            actual,
+            ..
+        } => {
+            let mut err = github_actions::error(
+                test_path,
+                format!("Wrong number of matching diagnostics{revision}"),
+            )
+            .line(line(span));
+            writeln!(err, "expected {expected}, but found {actual}").unwrap();
+        }
         Error::NoPatternsFound => {
             github_actions::error(
                 test_path,
@@ -61,7 +92,33 @@ fn gha_error(error: &Error, test_path: &str, revision: &str) {
             output: _,
             expected,
             bless_command,
+            normalization,
         } => {
+            if !normalization.is_empty() {
+                let mut err = github_actions::error(
+                    test_path,
+                    format!("Normalization rules applied, in order{revision}"),
+                );
+                for RuleApplication {
+                    span,
+                    rule,
+                    replacements,
+                } in normalization
+                {
+                    let location = if span.is_dummy() {
+                        "`Config`".into()
+                    } else {
+                        format!("{}:{}", display(&span.file), line(span))
+                    };
+                    let replacements = match replacements {
+                        0 => "never matched (dead)".into(),
+                        1 => "1 replacement".into(),
+                        n => format!("{n} replacements"),
+                    };
+                    writeln!(err, "{location}: {rule}: {replacements}").unwrap();
+                }
+            }
+
             if expected.is_empty() {
                 let mut err = github_actions::error(
                     test_path,
@@ -155,8 +212,13 @@ fn gha_error(error: &Error, test_path: &str, revision: &str) {
             }
         }
         Error::InvalidComment { msg, span } => {
-            let mut err = github_actions::error(test_path, format!("Could not parse comment"))
-                .line(line(span));
+            let path = if span.file.as_os_str().is_empty() {
+                test_path.to_string()
+            } else {
+                display(&span.file)
+            };
+            let mut err =
+                github_actions::error(path, format!("Could not parse comment")).line(line(span));
             writeln!(err, "{msg}").unwrap();
         }
         Error::MultipleRevisionsWithResults { kind, lines } => {
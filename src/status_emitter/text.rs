@@ -4,11 +4,14 @@ use super::Summary;
 use super::TestStatus;
 use crate::diagnostics::Level;
 use crate::diagnostics::Message;
+use crate::diff::DiffOptions;
 use crate::display;
+use crate::filter::RuleApplication;
 use crate::parser::Pattern;
 use crate::test_result::Errored;
 use crate::test_result::TestOk;
 use crate::test_result::TestResult;
+use crate::DiffFormat;
 use crate::Error;
 use crate::Errors;
 use crate::Format;
@@ -19,15 +22,16 @@ use colored::Colorize;
 use crossbeam_channel::{Sender, TryRecvError};
 #[cfg(feature = "indicatif")]
 use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
-use spanned::Span;
+use spanned::{Span, Spanned};
 use std::fmt::{Debug, Display};
 use std::io::Write as _;
 use std::path::Path;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 #[cfg(feature = "indicatif")]
 use std::{
-    sync::{atomic::AtomicUsize, atomic::Ordering, Arc, Mutex},
+    sync::{atomic::AtomicUsize, atomic::Ordering},
     thread::JoinHandle,
     time::Duration,
 };
@@ -49,6 +53,10 @@ pub struct Text {
     handle: Arc<JoinOnDrop>,
     #[cfg(feature = "indicatif")]
     ids: Arc<AtomicUsize>,
+    /// The ignored tests and why they were ignored, if they should be listed in the summary.
+    ignored_reasons: Option<Arc<Mutex<Vec<String>>>>,
+    /// How to render diffs of output files.
+    diff: DiffOptions,
 }
 
 #[cfg(feature = "indicatif")]
@@ -320,9 +328,38 @@ impl Text {
             handle: Arc::new(handle.into()),
             #[cfg(feature = "indicatif")]
             ids: Arc::new(AtomicUsize::new(1)),
+            ignored_reasons: None,
+            diff: DiffOptions::default(),
         }
     }
 
+    /// List the ignored tests and the rule that ignored them in the summary.
+    pub fn show_ignored_reasons(mut self) -> Self {
+        self.ignored_reasons = Some(Default::default());
+        self
+    }
+
+    /// Choose how differences between expected and actual output files are rendered.
+    pub fn diff_format(mut self, format: DiffFormat) -> Self {
+        self.diff.format = format;
+        self
+    }
+
+    /// Show `context` unchanged lines around each change in diffs (default: 2).
+    pub fn diff_context(mut self, context: usize) -> Self {
+        self.diff.context = context;
+        self
+    }
+
+    /// Show differences between expected and actual output files by running `tool` with the
+    /// paths of both files, like `git difftool`. The actual output is written to a temporary file.
+    /// `tool` may contain arguments separated by whitespace, e.g. `delta --side-by-side`.
+    /// [`Args::test`](crate::Args::test) reads it from the `UI_TEST_DIFF_TOOL` environment variable.
+    pub fn diff_tool(mut self, tool: impl Into<String>) -> Self {
+        self.diff.tool = Some(tool.into());
+        self
+    }
+
     /// Print one line per test that gets run.
     pub fn verbose() -> Self {
         Self::start_thread(OutputVerbosity::Full)
@@ -367,11 +404,33 @@ impl TestStatus for TextTest {
         if aborted {
             self.text.sender.send(Msg::Abort).unwrap();
         }
+        if let (Ok(TestOk::Ignored(reason)), Some(ignored_reasons)) =
+            (result, &self.text.ignored_reasons)
+        {
+            let revision = if self.revision.is_empty() {
+                String::new()
+            } else {
+                format!(" (revision `{}`)", self.revision)
+            };
+            // Rules from other files, like included ones, are shown with their file.
+            let location = match reason.span() {
+                Some(span) => match (span.file == self.path, span_line(span)) {
+                    (true, Some(line)) => format!(" (line {line})"),
+                    (false, Some(line)) => format!(" ({}:{line})", display(&span.file)),
+                    (_, None) => format!(" ({})", display(&span.file)),
+                },
+                None => String::new(),
+            };
+            ignored_reasons.lock().unwrap().push(format!(
+                "    {}{revision}: ignored: {reason}{location}",
+                display(&self.path)
+            ));
+        }
         let result = match result {
             _ if aborted => "aborted".white(),
             Ok(TestOk::Ok) => "ok".green(),
             Err(Errored { .. }) => "FAILED".bright_red().bold(),
-            Ok(TestOk::Ignored) => "ignored (in-test comment)".yellow(),
+            Ok(TestOk::Ignored(reason)) => format!("ignored ({reason})").yellow(),
         };
         let new_leftover_msg = format!("... {result}");
         #[cfg(feature = "indicatif")]
@@ -554,16 +613,18 @@ impl StatusEmitter for Text {
         // Print all errors in a single thread to show reliable output
         struct Summarizer {
             failures: Vec<String>,
+            ignored_reasons: Vec<String>,
             succeeded: usize,
             ignored: usize,
             filtered: usize,
             aborted: bool,
+            diff: DiffOptions,
         }
 
         impl Summary for Summarizer {
             fn test_failure(&mut self, status: &dyn TestStatus, errors: &Errors) {
                 for error in errors {
-                    print_error(error, status.path());
+                    print_error(error, status.path(), &self.diff);
                 }
 
                 self.failures.push(if status.revision().is_empty() {
@@ -580,6 +641,13 @@ impl StatusEmitter for Text {
 
         impl Drop for Summarizer {
             fn drop(&mut self) {
+                if !self.ignored_reasons.is_empty() {
+                    println!();
+                    println!("{}", "IGNORED:".yellow().underline().bold());
+                    for line in &self.ignored_reasons {
+                        println!("{line}");
+                    }
+                }
                 if self.failures.is_empty() {
                     println!();
                     if self.aborted {
@@ -618,17 +686,28 @@ impl StatusEmitter for Text {
                 println!();
             }
         }
+        let ignored_reasons = self
+            .ignored_reasons
+            .as_ref()
+            .map(|reasons| {
+                let mut reasons = std::mem::take(&mut *reasons.lock().unwrap());
+                reasons.sort();
+                reasons
+            })
+            .unwrap_or_default();
         Box::new(Summarizer {
             failures: vec![],
+            ignored_reasons,
             succeeded,
             ignored,
             filtered,
             aborted,
+            diff: self.diff.clone(),
         })
     }
 }
 
-fn print_error(error: &Error, path: &Path) {
+fn print_error(error: &Error, path: &Path, diff: &DiffOptions) {
     /// Every error starts with a header like that, to make them all easy to find.
     /// It is made to look like the headers printed for spanned errors.
     fn print_error_header(msg: impl Display) {
@@ -656,40 +735,58 @@ fn print_error(error: &Error, path: &Path) {
         Error::PatternNotFound {
             pattern,
             expected_line,
+            near_misses,
         } => {
-            let line = match expected_line {
-                Some(line) => format!("on line {line}"),
-                None => format!("outside the testfile"),
-            };
             let msg = match &**pattern {
                 Pattern::SubString(s) => {
-                    format!("`{s}` not found in diagnostics {line}")
+                    format!("`{s}` not found in diagnostics {expected_line}")
                 }
                 Pattern::Regex(r) => {
-                    format!("`/{r}/` does not match diagnostics {line}",)
+                    format!("`/{r}/` does not match diagnostics {expected_line}",)
                 }
             };
-            // This will print a suitable error header.
-            create_error(
-                msg,
-                &[&[("expected because of this pattern", pattern.span())]],
-                path,
+            let near_misses: Vec<_> = near_misses
+                .iter()
+                .map(|(line, level, msg)| {
+                    format!(
+                        "did you mean `{}: {msg}` on line {line}?",
+                        level.annotation_name()
+                    )
+                })
+                .collect();
+            let mut labels = vec![("expected because of this pattern", pattern.span())];
+            labels.extend(
+                near_misses
+                    .iter()
+                    .map(|near_miss| (near_miss.as_str(), Span::default())),
             );
+            // This will print a suitable error header.
+            create_error(msg, &[&labels], path);
         }
         Error::CodeNotFound {
             code,
             expected_line,
         } => {
-            let line = match expected_line {
-                Some(line) => format!("on line {line}"),
-                None => format!("outside the testfile"),
-            };
             create_error(
-                format!("diagnostic code `{}` not found {line}", &**code),
+                format!("diagnostic code `{}` not found {expected_line}", &**code),
                 &[&[("expected because of this pattern", code.span())]],
                 path,
             );
         }
+        Error::WrongMatchCount {
+            span,
+            expected_line,
+            expected,
+            actual,
+        } => {
+            create_error(
+                format!(
+                    "expected {expected} diagnostics matching this pattern {expected_line}, but found {actual}"
+                ),
+                &[&[("expected because of this pattern", span.clone())]],
+                path,
+            );
+        }
         Error::NoPatternsFound => {
             print_error_header("expected error patterns, but found none");
         }
@@ -709,6 +806,7 @@ fn print_error(error: &Error, path: &Path) {
             output,
             expected,
             bless_command,
+            normalization,
         } => {
             let bless = || {
                 if let Some(bless_command) = bless_command {
@@ -743,16 +841,48 @@ fn print_error(error: &Error, path: &Path) {
             } else {
                 print_error_header("actual output differed from expected");
                 bless();
-                println!("{}", format!("--- {}", display(output_path)).red());
-                println!(
-                    "{}",
-                    format!(
-                        "+++ <{} output>",
-                        output_path.extension().unwrap().to_str().unwrap()
-                    )
-                    .green()
-                );
-                crate::diff::print_diff(expected, actual);
+                let ran_tool = diff.tool.as_ref().is_some_and(|tool| {
+                    crate::diff::run_diff_tool(tool, output_path, actual)
+                        .map_err(|err| println!("failed to run diff tool `{tool}`: {err}"))
+                        .is_ok()
+                });
+                if !ran_tool {
+                    if diff.format == DiffFormat::Raw {
+                        // Label both sides like `git diff`, so that `patch -p1` and
+                        // `git apply` can update the output file with the diff.
+                        let path = display(output_path);
+                        println!("--- a/{path}\n+++ b/{path}");
+                    } else {
+                        let old = format!("--- {}", display(output_path));
+                        let new = format!(
+                            "+++ <{} output>",
+                            output_path.extension().unwrap().to_str().unwrap()
+                        );
+                        println!("{}\n{}", old.red(), new.green());
+                    }
+                    crate::diff::print_diff(expected, actual, diff);
+                }
+
+                if !normalization.is_empty() {
+                    println!("Normalization rules applied, in order:");
+                    for RuleApplication {
+                        span,
+                        rule,
+                        replacements,
+                    } in normalization
+                    {
+                        let location = match span_line(span) {
+                            Some(line) => format!("{}:{line}", display(&span.file)),
+                            None => "`Config`".into(),
+                        };
+                        let replacements = match replacements {
+                            0 => "never matched (dead)".yellow(),
+                            1 => "1 replacement".normal(),
+                            n => format!("{n} replacements").normal(),
+                        };
+                        println!("    {location}: {rule}: {replacements}");
+                    }
+                }
 
                 println!(
                     "Full unnormalized output:\n{}",
@@ -827,7 +957,7 @@ fn print_error(error: &Error, path: &Path) {
                 &aux_path.span.file,
             );
             for error in errors {
-                print_error(error, aux_path);
+                print_error(error, aux_path, diff);
             }
         }
         Error::Rustfix(error) => {
@@ -845,10 +975,20 @@ fn print_error(error: &Error, path: &Path) {
 
 #[allow(clippy::type_complexity)]
 fn create_error(s: impl AsRef<str>, lines: &[&[(&str, Span)]], file: &Path) {
-    let source = std::fs::read_to_string(file).unwrap();
-    let file = display(file);
+    // Spans can point into other files than the test, e.g. into directory defaults.
+    let sources = lines
+        .iter()
+        .map(|label| {
+            let file = label
+                .iter()
+                .map(|(_, span)| &span.file)
+                .find(|path| !path.as_os_str().is_empty())
+                .map_or(file, |path| path.as_path());
+            (display(file), std::fs::read_to_string(file).unwrap())
+        })
+        .collect::<Vec<_>>();
     let mut msg = annotate_snippets::Level::Error.title(s.as_ref());
-    for &label in lines {
+    for (&label, (file, source)) in lines.iter().zip(&sources) {
         let annotations = label
             .iter()
             .filter(|(_, span)| !span.is_dummy())
@@ -859,9 +999,9 @@ fn create_error(s: impl AsRef<str>, lines: &[&[(&str, Span)]], file: &Path) {
             })
             .collect::<Vec<_>>();
         if !annotations.is_empty() {
-            let snippet = Snippet::source(&source)
+            let snippet = Snippet::source(source)
                 .fold(true)
-                .origin(&file)
+                .origin(file)
                 .annotations(annotations);
             msg = msg.snippet(snippet);
         }
@@ -878,3 +1018,15 @@ fn create_error(s: impl AsRef<str>, lines: &[&[(&str, Span)]], file: &Path) {
     };
     println!("{}", renderer.render(msg));
 }
+
+/// The one-based line of the start of `span`, if it points into a file.
+fn span_line(span: &Span) -> Option<usize> {
+    if span.is_dummy() {
+        return None;
+    }
+    let file = Spanned::read_from_file(&span.file).ok()?;
+    let line = file
+        .lines()
+        .position(|line| line.span.bytes.contains(&span.bytes.start))?;
+    Some(line + 1)
+}
@@ -1,8 +1,9 @@
 //! Various data structures used for carrying information about test success or failure
 
-use crate::{status_emitter::TestStatus, AbortCheck, Error};
+use crate::{status_emitter::TestStatus, AbortCheck, Condition, Error};
 use bstr::ByteSlice;
 use color_eyre::eyre::Result;
+use spanned::{Span, Spanned};
 
 /// The possible non-failure results a single test can have.
 #[derive(Debug)]
@@ -10,7 +11,55 @@ pub enum TestOk {
     /// The test passed
     Ok,
     /// The test was ignored due to a rule (`//@only-*` or `//@ignore-*`)
-    Ignored,
+    Ignored(IgnoreReason),
+}
+
+/// The rule that caused a test to be ignored.
+#[derive(Debug, Clone)]
+pub enum IgnoreReason {
+    /// An `//@ignore-*` condition was met.
+    Ignore(Spanned<Condition>),
+    /// An `//@only-*` condition was not met.
+    Only(Spanned<Condition>),
+    /// A custom flag like `//@needs-asm-support` filtered out the test.
+    Flag {
+        /// The name of the flag.
+        name: &'static str,
+        /// Where the flag was set.
+        span: Span,
+    },
+    /// Only ignored tests are run (`--ignored`), and this test is not ignored.
+    NotIgnored,
+}
+
+impl IgnoreReason {
+    /// The place in the test file that caused the test to be ignored, if any.
+    pub fn span(&self) -> Option<&Span> {
+        let span = match self {
+            IgnoreReason::Ignore(cond) | IgnoreReason::Only(cond) => &cond.span,
+            IgnoreReason::Flag { span, .. } => span,
+            IgnoreReason::NotIgnored => return None,
+        };
+        (!span.is_dummy()).then_some(span)
+    }
+}
+
+/// Renders the rule like it was written in the test, e.g. `only-target: wasm32`.
+impl std::fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IgnoreReason::Ignore(cond) => write!(f, "ignore-{}", **cond),
+            IgnoreReason::Only(cond) => match **cond {
+                // These are commands of their own instead of `only-*` conditions.
+                Condition::MinRustcVersion(_) | Condition::MaxRustcVersion(_) => {
+                    write!(f, "{}", **cond)
+                }
+                _ => write!(f, "only-{}", **cond),
+            },
+            IgnoreReason::Flag { name, .. } => write!(f, "{name}"),
+            IgnoreReason::NotIgnored => write!(f, "only ignored tests are run"),
+        }
+    }
 }
 
 /// The possible results a single test can have.
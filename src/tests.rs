@@ -1,6 +1,7 @@
 use super::*;
 use crate::diagnostics::Level;
 use crate::diagnostics::Message;
+use bstr::ByteSlice;
 use spanned::{Span, Spanned};
 use std::path::PathBuf;
 
@@ -12,9 +13,51 @@ fn config() -> Config {
     }
 }
 
+/// An empty directory in the system's temp dir that gets removed again on drop,
+/// even if the test panics.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` must be unique among the tests, as they run in parallel.
+    pub(crate) fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("ui_test_{name}_{}", std::process::id()));
+        // Leftovers of a previous run that got killed.
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Parses `$s` as the test file at `$path` (`moobar` by default) and replaces
+/// `$config` with a `TestConfig` for `$revision` of it.
 macro_rules! config {
     ($config:ident = $s:expr) => {
-        let path = Path::new("moobar");
+        config!($config = $s, "moobar");
+    };
+    ($config:ident = $s:expr, $path:expr) => {
+        config!($config = $s, $path, "");
+    };
+    ($config:ident = $s:expr, $path:expr, $revision:expr) => {
+        let path = PathBuf::from(&$path);
         let comments = Comments::parse(
             Spanned::new(
                 $s.as_bytes(),
@@ -32,8 +75,8 @@ macro_rules! config {
             comments: Arc::new(comments),
             aux_dir: PathBuf::from("unused_doesnt_exist"),
             status: Box::new(crate::status_emitter::SilentStatus {
-                path: path.to_path_buf(),
-                revision: String::new(),
+                path,
+                revision: String::from($revision),
             }),
         };
     };
@@ -248,6 +291,85 @@ fn main() {
     }
 }
 
+#[test]
+fn pattern_near_misses() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32;
+    //~^^ ERROR: mismatched types
+}
+    ";
+    let config = config();
+    config!(config = s);
+    let message = |message: &str| Message {
+        message: message.to_string(),
+        level: Level::Error,
+        line: None,
+        span: None,
+        code: None,
+    };
+    let messages = vec![
+        vec![],
+        vec![message("unused variable")],
+        vec![],
+        vec![message("mismatchd types"), message("mismatched types")],
+    ];
+    let mut errors = vec![];
+    config
+        .check_annotations(messages, vec![], &mut errors)
+        .unwrap();
+    match &errors[..] {
+        [Error::PatternNotFound { near_misses, .. }, ..] => {
+            let near_misses: Vec<_> = near_misses
+                .iter()
+                .map(|(line, level, msg)| (line.get(), *level, msg.as_str()))
+                .collect();
+            assert_eq!(
+                near_misses,
+                [
+                    (3, Level::Error, "mismatched types"),
+                    (3, Level::Error, "mismatchd types")
+                ]
+            );
+        }
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn pattern_near_misses_of_other_level() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32;
+    //~^ ERROR: mismatched types
+}
+    ";
+    let config = config();
+    config!(config = s);
+    let message = |level: Level| Message {
+        message: "mismatched types".to_string(),
+        level,
+        line: None,
+        span: None,
+        code: None,
+    };
+    let messages = vec![vec![], vec![], vec![], vec![message(Level::Ice)]];
+    let mut errors = vec![];
+    config
+        .check_annotations(messages, vec![], &mut errors)
+        .unwrap();
+    match &errors[..] {
+        [Error::PatternNotFound { near_misses, .. }, ..] => {
+            let near_misses: Vec<_> = near_misses
+                .iter()
+                .map(|(line, level, msg)| (line.get(), *level, msg.as_str()))
+                .collect();
+            assert_eq!(near_misses, [(3, Level::Ice, "mismatched types")]);
+        }
+        _ => panic!("{:#?}", errors),
+    }
+}
+
 #[test]
 fn missing_warn_pattern() {
     let s = r"
@@ -507,3 +629,810 @@ fn main() {
         }
     }
 }
+
+#[test]
+fn find_leveled_code() {
+    let s = r"
+fn main() {
+    let x = 0; //~ WARN unused_variables
+}
+    ";
+    let mut config = config();
+    config.comment_defaults.base().diagnostic_code_prefix =
+        Spanned::dummy("clippy::".to_string()).into();
+    config!(config = s);
+    let message = |level| Message {
+        message: "unused variable: `x`".to_string(),
+        level,
+        line: None,
+        span: None,
+        code: Some("clippy::unused_variables".into()),
+    };
+    {
+        let messages = vec![vec![], vec![], vec![], vec![message(Level::Warn)]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [] => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+
+    // error instead of warning
+    {
+        let messages = vec![vec![], vec![], vec![], vec![message(Level::Error)]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [Error::CodeNotFound { code, .. }, Error::ErrorsWithoutPattern { msgs, .. }]
+                if **code == "clippy::unused_variables"
+                    && line!(code.span, s) == 3
+                    && msgs.len() == 1 => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+}
+
+#[test]
+fn find_counted_pattern() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32; //~ ERROR[x2]: mismatched types
+}
+    ";
+    let config = config();
+    config!(config = s);
+    let message = || Message {
+        message: "mismatched types".to_string(),
+        level: Level::Error,
+        line: None,
+        span: None,
+        code: Some("E0308".into()),
+    };
+    {
+        let messages = vec![vec![], vec![], vec![], vec![message(), message()]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [] => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+
+    // too few matching diagnostics
+    {
+        let messages = vec![vec![], vec![], vec![], vec![message()]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [Error::WrongMatchCount {
+                span,
+                expected_line: ExpectedLine::Line(expected_line),
+                expected,
+                actual: 1,
+            }] if expected.get() == 2 && expected_line.get() == 3 && line!(span, s) == 3 => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+
+    // too many matching diagnostics, none of them are reported as unmatched
+    {
+        let messages = vec![
+            vec![],
+            vec![],
+            vec![],
+            vec![message(), message(), message()],
+        ];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [Error::WrongMatchCount {
+                expected,
+                actual: 3,
+                ..
+            }] if expected.get() == 2 => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+}
+
+#[test]
+fn find_pattern_anywhere() {
+    let s = r"
+fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched types
+}
+//~? ERROR: mismatched types
+    ";
+    let config = config();
+    config!(config = s);
+    let message = || Message {
+        message: "mismatched types".to_string(),
+        level: Level::Error,
+        line: None,
+        span: None,
+        code: Some("E0308".into()),
+    };
+    // the anchored pattern claims line 3, the `//~?` one picks up line 1
+    {
+        let messages = vec![vec![], vec![message()], vec![], vec![message()]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [] => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+
+    // the anchored pattern takes precedence, so nothing is left for the `//~?` one
+    {
+        let messages = vec![vec![], vec![], vec![], vec![message()]];
+        let mut errors = vec![];
+        config
+            .check_annotations(messages, vec![], &mut errors)
+            .unwrap();
+        match &errors[..] {
+            [Error::PatternNotFound {
+                pattern,
+                expected_line: ExpectedLine::Anywhere,
+                ..
+            }] if line!(pattern.span, s) == 5 => {}
+            _ => panic!("{:#?}", errors),
+        }
+    }
+}
+
+#[test]
+fn bless_annotations() {
+    let s = "fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched typos
+    let _y = 1; // some comment
+    foo();
+    //~^^ ERROR: cannot find function `foo`
+    bar(); //~ ERROR: stale
+}
+";
+    let dir = TempDir::new("bless_annotations");
+    let path = dir.join("foo.rs");
+    std::fs::write(&path, s).unwrap();
+    let config = config();
+    config!(config = s, path);
+    let message = |message: &str| Message {
+        message: message.to_string(),
+        level: Level::Error,
+        line: None,
+        span: None,
+        code: None,
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![message("mismatched types")],
+        vec![message("unused"), message("also unused")],
+        vec![message("cannot find function `foo`")],
+    ];
+    let mut errors = vec![];
+    config
+        .check_annotations(messages, vec![], &mut errors)
+        .unwrap();
+    config.bless_annotations(&mut errors);
+    let blessed = std::fs::read_to_string(&path).unwrap();
+    match &errors[..] {
+        [] => {}
+        _ => panic!("{:#?}", errors),
+    }
+    assert_eq!(
+        blessed,
+        "fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched types
+    let _y = 1; // some comment
+    //~^ ERROR: unused
+    //~| ERROR: also unused
+    foo();
+    //~^ ERROR: cannot find function `foo`
+    bar();
+}
+"
+    );
+}
+
+#[test]
+fn bless_two_annotations_on_one_line() {
+    let s = "fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched typos
+}
+";
+    let dir = TempDir::new("bless_two_annotations");
+    let path = dir.join("foo.rs");
+    std::fs::write(&path, s).unwrap();
+    let config = config();
+    config!(config = s, path);
+    // A second annotation on the same line, as a custom comment parser could add it.
+    let base = Arc::get_mut(&mut config.comments).unwrap().base();
+    let start = s.find("typos").unwrap();
+    let note = crate::parser::ErrorMatch {
+        kind: crate::parser::ErrorMatchKind::Pattern {
+            pattern: Spanned::new(
+                crate::parser::Pattern::SubString("typos".into()),
+                Span {
+                    file: path.clone(),
+                    bytes: start..start + 5,
+                },
+            ),
+            level: Level::Note,
+        },
+        ..base.error_matches[0].clone()
+    };
+    base.error_matches.push(note);
+    let message = |level, message: &str| Message {
+        message: message.to_string(),
+        level,
+        line: None,
+        span: None,
+        code: None,
+    };
+    let messages = vec![
+        vec![],
+        vec![],
+        vec![
+            message(Level::Error, "mismatched types"),
+            message(Level::Note, "expected `i32`"),
+        ],
+    ];
+    let mut errors = vec![];
+    config
+        .check_annotations(messages, vec![], &mut errors)
+        .unwrap();
+    config.bless_annotations(&mut errors);
+    let blessed = std::fs::read_to_string(&path).unwrap();
+    match &errors[..] {
+        [] => {}
+        _ => panic!("{:#?}", errors),
+    }
+    assert_eq!(
+        blessed,
+        "fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched types
+    //~^ NOTE: expected `i32`
+}
+"
+    );
+}
+
+#[test]
+fn bless_annotations_with_revisions() {
+    let s = "//@revisions: a b
+fn main() {
+    let _x: i32 = 0u32; //~ ERROR: mismatched typos
+}
+";
+    let config = config();
+    config!(
+        config = s,
+        "ui_test_bless_annotations_with_revisions.rs",
+        "a"
+    );
+    let mut errors = vec![];
+    config
+        .check_annotations(vec![], vec![], &mut errors)
+        .unwrap();
+    config.bless_annotations(&mut errors);
+    // The file is left alone, which is reported instead of silently keeping the errors.
+    match &errors[..] {
+        [Error::PatternNotFound { .. }, Error::ConfigError(msg)] => {
+            assert!(msg.contains("has revisions"), "{msg}")
+        }
+        _ => panic!("{:#?}", errors),
+    }
+}
+
+#[test]
+fn ignore_reason() {
+    let s = r"
+//@ignore-host: wasm32
+//@only-target: wasm32
+fn main() {}
+    ";
+    let mut config = config();
+    config.host = Some("x86_64-unknown-linux-gnu".into());
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    config!(config = s);
+    let reason = config
+        .config
+        .ignore_reason(&config.comments, "")
+        .unwrap()
+        .expect("test should be ignored");
+    assert_eq!(reason.to_string(), "only-target: wasm32");
+    assert_eq!(line!(reason.span().unwrap(), s), 3);
+    assert!(!config.config.test_file_conditions(&config.comments, ""));
+
+    config.config.run_only_ignored = true;
+    assert!(config
+        .config
+        .ignore_reason(&config.comments, "")
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn version_condition_needs_compiler_version() {
+    let s = r"
+//@min-rustc-version: 1.80
+fn main() {}
+    ";
+    let mut config = config();
+    config.host = Some("x86_64-unknown-linux-gnu".into());
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    config!(config = s);
+    let errored = config
+        .config
+        .ignore_reason(&config.comments, "")
+        .expect_err("the version of `cake` is unknown");
+    assert!(matches!(errored.errors[..], [Error::ConfigError(_)]));
+    assert!(!config.config.test_file_conditions(&config.comments, ""));
+
+    config.config.compiler_version = Some(
+        rustc_version::version_meta_for(
+            "rustc 1.79.0 (129f3b996 2024-06-10)\n\
+             commit-hash: unknown\n\
+             commit-date: unknown\n\
+             host: x86_64-unknown-linux-gnu\n\
+             release: 1.79.0\n",
+        )
+        .unwrap(),
+    );
+    let reason = config
+        .config
+        .ignore_reason(&config.comments, "")
+        .unwrap()
+        .expect("test should be ignored");
+    assert_eq!(reason.to_string(), "min-rustc-version: 1.80.0");
+}
+
+#[test]
+#[cfg(feature = "rustc")]
+fn cfg_condition_needs_cfgs() {
+    let s = r"
+//@only-cfg: unix
+fn main() {}
+    ";
+    let mut config = config();
+    config.host = Some("x86_64-unknown-linux-gnu".into());
+    config.target = Some("x86_64-unknown-linux-gnu".into());
+    config!(config = s);
+    let errored = config
+        .config
+        .ignore_reason(&config.comments, "")
+        .expect_err("`cake` can't print cfgs");
+    assert!(matches!(errored.errors[..], [Error::ConfigError(_)]));
+    assert!(!config.config.test_file_conditions(&config.comments, ""));
+
+    config.config.target_cfgs = TargetCfgs::new(vec!["unix".parse().unwrap()]);
+    assert!(config
+        .config
+        .ignore_reason(&config.comments, "")
+        .unwrap()
+        .is_none());
+    config.config.target_cfgs = TargetCfgs::new(vec![]);
+    let reason = config
+        .config
+        .ignore_reason(&config.comments, "")
+        .unwrap()
+        .expect("test should be ignored");
+    assert_eq!(reason.to_string(), "only-cfg: unix");
+}
+
+#[test]
+fn shared_snapshot() {
+    let s = "//@revisions: a b c
+//@shared-snapshot
+fn main() {}
+";
+    let dir = TempDir::new("shared_snapshot");
+    let path = dir.join("foo.rs");
+    let run = |outputs: [&str; 3], handling: OutputConflictHandling| {
+        let mut errors = vec![];
+        for (revision, output) in ["a", "b", "c"].into_iter().zip(outputs) {
+            let config = Config {
+                output_conflict_handling: handling,
+                ..config()
+            };
+            config!(config = s, path, revision);
+            config.check_output(output.as_bytes(), &mut errors, "stderr");
+        }
+        errors
+    };
+    let files = || {
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let content = std::fs::read_to_string(&path).unwrap();
+                (
+                    path.file_name().unwrap().to_str().unwrap().to_owned(),
+                    content,
+                )
+            })
+            .collect();
+        files.sort();
+        files
+    };
+
+    assert!(run(["x", "x", "y"], bless_output_files).is_empty());
+    assert_eq!(
+        files(),
+        [
+            ("foo.c.stderr".to_owned(), "y".to_owned()),
+            ("foo.stderr".to_owned(), "x".to_owned())
+        ]
+    );
+    assert!(run(["x", "x", "y"], error_on_output_conflict).is_empty());
+    assert_eq!(run(["x", "y", "y"], error_on_output_conflict).len(), 1);
+
+    // Once the outputs agree again, blessing removes the separate file.
+    assert!(run(["x", "x", "x"], bless_output_files).is_empty());
+    assert_eq!(files(), [("foo.stderr".to_owned(), "x".to_owned())]);
+}
+
+#[test]
+fn shared_snapshot_owner_is_run() {
+    let s = "//@revisions: a b
+//@[a]ignore-on-host
+//@shared-snapshot
+fn main() {}
+";
+    let dir = TempDir::new("shared_snapshot_owner");
+    let config = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        output_conflict_handling: bless_output_files,
+        ..config()
+    };
+    config!(config = s, dir.join("foo.rs"), "b");
+    // `a` is ignored, so `b` owns the shared file.
+    let mut errors = vec![];
+    let written = config.check_output(b"x", &mut errors, "stderr");
+    assert!(errors.is_empty(), "{errors:#?}");
+    assert_eq!(written, dir.join("foo.stderr"));
+    assert_eq!(std::fs::read_to_string(&written).unwrap(), "x");
+    assert!(!dir.join("foo.b.stderr").exists());
+}
+
+#[test]
+fn config_from_file() {
+    let dir = TempDir::new("config_from_file");
+    std::fs::write(
+        dir.join("defaults.ui"),
+        "//@compile-flags: --edition=2018\n//@check-pass\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("ui_test.toml"),
+        r#"
+base = "dummy"
+root_dir = "tests/ui"
+program = "cake"
+args = ["--frosting"]
+envs = { FLAVOUR = "chocolate" }
+threads = 3
+bless_command = "bake --bless"
+filters = [["[0-9]+ms", "$$TIME"]]
+stderr_filters = [["oven", "stove"]]
+comment_defaults = "defaults.ui"
+"#,
+    )
+    .unwrap();
+    let config = Config::from_file(dir.join("ui_test.toml")).unwrap();
+    assert_eq!(config.root_dir, dir.join("tests/ui"));
+    assert_eq!(config.program.program, PathBuf::from("cake"));
+    assert_eq!(config.program.args, ["--frosting"]);
+    assert_eq!(
+        config.program.envs,
+        [("FLAVOUR".into(), Some("chocolate".into()))]
+    );
+    assert_eq!(config.threads, std::num::NonZeroUsize::new(3));
+    assert_eq!(config.bless_command.as_deref(), Some("bake --bless"));
+    let base = &config.comment_defaults.revisioned[&[][..]];
+    assert_eq!(base.normalize_stderr.len(), 2);
+    assert_eq!(base.normalize_stdout.len(), 1);
+    assert_eq!(base.compile_flags, ["--edition=2018"]);
+    assert_eq!(
+        base.exit_status.as_ref().unwrap().span().file,
+        dir.join("defaults.ui")
+    );
+
+    std::fs::write(
+        dir.join("defaults.ui"),
+        "//@check-pass\n//@ compile-flag: -O\n",
+    )
+    .unwrap();
+    let err = Config::from_file(dir.join("ui_test.toml")).unwrap_err();
+    let location = format!("{}:2:5: ", dir.join("defaults.ui").display());
+    assert!(
+        err.to_string().contains(&location) && err.to_string().contains("`compile-flag`"),
+        "{err}"
+    );
+
+    std::fs::write(dir.join("ui_test.toml"), "root_dir = \"x\"\nthreadz = 3\n").unwrap();
+    let err = Config::from_file(dir.join("ui_test.toml")).unwrap_err();
+    assert!(format!("{err:?}").contains("threadz"), "{err:?}");
+}
+
+#[test]
+fn builtin_filters() {
+    let normalize = |name: &str, text: &str| {
+        let (filter, replacement) = Match::builtin(name).unwrap();
+        filter
+            .replace_all(text.as_bytes(), replacement, Path::new("tests/ui/foo.rs"))
+            .0
+            .to_str()
+            .unwrap()
+            .to_owned()
+    };
+    assert_eq!(
+        normalize(
+            "sysroot",
+            "--> /home/me/.rustup/toolchains/nightly/lib/rustlib/src/rust/library/core/src/num/mod.rs:12:5"
+        ),
+        "--> $SRC_DIR/core/src/num/mod.rs:12:5"
+    );
+    assert_eq!(
+        normalize(
+            "sysroot",
+            "at /rustc/3f5fd8dd41153bc5fdca9427e9e05be2c767ba23/library/std/src/panicking.rs"
+        ),
+        "at $SRC_DIR/std/src/panicking.rs"
+    );
+    assert_eq!(
+        normalize("test-dir", "--> tests/ui/foo.rs:1:1 and tests/uifoo.rs"),
+        "--> $DIR/foo.rs:1:1 and tests/uifoo.rs"
+    );
+    assert_eq!(
+        normalize(
+            "rustc-hash",
+            "_ZN3foo3bar17h0123456789abcdefE foo::bar::h0123456789abcdef libfoo-fedcba9876543210.rlib"
+        ),
+        "_ZN3foo3bar17hHASHE foo::bar::hHASH libfoo-HASH.rlib"
+    );
+    assert_eq!(
+        normalize(
+            "std-line-numbers",
+            "--> $SRC_DIR/core/src/num/mod.rs:12:5 and src/main.rs:3:4"
+        ),
+        "--> $SRC_DIR/core/src/num/mod.rs:LL:COL and src/main.rs:3:4"
+    );
+    assert_eq!(
+        normalize(
+            "error-counts",
+            "aborting due to 3 previous errors; 1 warning emitted"
+        ),
+        "aborting due to NN previous errors; NN warning emitted"
+    );
+    assert!(Match::builtin("sysrot").is_none());
+
+    let mut config = config();
+    config.builtin_filter("error-counts").unwrap();
+    assert_eq!(config.comment_defaults.base().normalize_stderr.len(), 1);
+    assert!(config.builtin_filter("sysrot").is_err());
+}
+
+#[test]
+fn trace_normalization() {
+    let s = r#"//@normalize-stderr-test: "[0-9]+ms" -> "$$TIME"
+//@normalize-stderr-test: "never there" -> "gone"
+fn main() {}
+"#;
+    let config = config();
+    config!(config = s, "$DIR/foo.rs");
+    let (normalized, trace) = config.normalize_traced(b"took 12ms, then 7ms", "stderr");
+    assert_eq!(normalized, b"took $TIME, then $TIME");
+    assert_eq!(
+        trace[1].span.bytes.start,
+        s.find("normalize-stderr-test: \"never").unwrap()
+    );
+    let trace = trace
+        .iter()
+        .map(|application| (application.rule.as_str(), application.replacements))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        trace,
+        [
+            ("`[0-9]+ms` -> `$$TIME`", 2),
+            ("`never there` -> `gone`", 0)
+        ]
+    );
+}
+
+#[test]
+fn builtin_comparisons() {
+    use crate::compare::*;
+    assert!(exact(b"a\nb\n", b"a\nb\n"));
+    assert!(!exact(b"a \nb\n", b"a\nb\n"));
+
+    assert!(trailing_whitespace(b"a \nb\t\n\n", b"a\nb\n"));
+    assert!(!trailing_whitespace(b" a\nb\n", b"a\nb\n"));
+
+    assert!(unordered(b"b\na\na\n", b"a\nb\na\n"));
+    assert!(!unordered(b"b\na\n", b"a\nb\na\n"));
+
+    assert!(subset(b"c\nb\na\n", b"a\nb\n"));
+    assert!(!subset(b"a\nb\n", b"a\na\n"));
+}
+
+#[test]
+fn wildcard_comparison() {
+    use crate::compare::*;
+    let expected = b"error: foo [..] bar\n...\nnote: [..]\n";
+    assert!(wildcards(b"error: foo 42 bar\na\nb\nnote: x\n", expected));
+    assert!(wildcards(b"error: foo  bar\nnote: \n", expected));
+    assert!(!wildcards(b"error: foo 42 baz\nnote: x\n", expected));
+    assert!(!wildcards(b"error: foo 42 bar\na\n", expected));
+    assert!(wildcards(b"a[..]b\n", b"a[..]b\n"));
+    assert!(wildcards(b"abab\n", b"a[..]b[..]b\n"));
+    assert!(!wildcards(b"ab\n", b"a[..]b[..]b\n"));
+
+    assert_eq!(
+        preserve_wildcards(b"error: foo 43 bar\na\nnote: y\n", expected).to_str_lossy(),
+        "error: foo [..] bar\n...\nnote: [..]\n"
+    );
+    assert_eq!(
+        preserve_wildcards(
+            b"error: foo 43 bar\nnew: y\n",
+            b"error: foo [..] bar\nold: x\n"
+        )
+        .to_str_lossy(),
+        "error: foo [..] bar\nnew: y\n"
+    );
+    // Wildcards are opt-in, by default `[..]` is just slice syntax.
+    let config = config();
+    config!(config = "fn main() {}\n");
+    assert!(!config.comparison("stderr")(b"&x[1..]\n", b"&x[..]\n"));
+    let mut config = config.config;
+    config.output_comparison("stderr", wildcards);
+    config!(config = "fn main() {}\n");
+    assert!(config.comparison("run.stderr")(b"&x[1..]\n", b"&x[..]\n"));
+    assert!(!config.comparison("stdout")(b"&x[1..]\n", b"&x[..]\n"));
+
+    assert_eq!(
+        preserve_wildcards(b"a\nb\nc\n", b"a\n...\n").to_str_lossy(),
+        "a\n...\n"
+    );
+}
+
+#[test]
+fn check_single_file() {
+    let dir = TempDir::new("check_file");
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    std::fs::write(dir.join(crate::DEFAULTS_FILE_NAME), "//@ignore-on-host\n").unwrap();
+    std::fs::write(dir.join("sub/foo.rs"), "//@revisions: a b\nfn main() {}\n").unwrap();
+    std::fs::write(dir.join("sub/bar.rs"), "//@bogus\nfn main() {}\n").unwrap();
+    let config = Config {
+        root_dir: dir.to_path_buf(),
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        ..config()
+    };
+
+    let runs = crate::check_file(config.clone(), &dir.join("sub/foo.rs")).unwrap();
+    let revisions: Vec<_> = runs.iter().map(|run| run.status.revision()).collect();
+    assert_eq!(revisions, ["a", "b"]);
+    assert!(runs
+        .iter()
+        .all(|run| matches!(run.result, Ok(TestOk::Ignored(_)))));
+
+    let runs = crate::check_file(config, &dir.join("sub/bar.rs")).unwrap();
+    assert_eq!(runs.len(), 1);
+    let Err(errored) = &runs[0].result else {
+        panic!("parsing `//@bogus` should fail")
+    };
+    assert_eq!(errored.errors.len(), 1);
+}
+
+#[test]
+#[cfg(feature = "rustc")]
+fn check_in_memory_tests() {
+    let out_dir = TempDir::new("in_memory");
+    let config = Config {
+        out_dir: out_dir.to_path_buf(),
+        ..Config::rustc("unused")
+    };
+    let source = b"//@no-rustfix\nfn main() {\n    let x: u8 = 300;\n    //~^ ERROR: literal out of range\n}\n";
+    let test = |stderr: &[u8]| crate::InMemoryTest {
+        path: PathBuf::from("lints/overflow.rs"),
+        source: source.to_vec(),
+        snapshots: [("stderr".to_owned(), stderr.to_vec())].into(),
+    };
+
+    let runs = crate::check_in_memory(config.clone(), &mut [test(b"wrong")]).unwrap();
+    assert_eq!(runs.len(), 1);
+    let Err(errored) = &runs[0].result else {
+        panic!("the snapshot should not match")
+    };
+    let [Error::OutputDiffers { actual, .. }] = &errored.errors[..] else {
+        panic!("{:?}", errored.errors)
+    };
+    // The output refers to the virtual path, so it can be used as the expected output.
+    assert!(actual.contains_str(" --> lints/overflow.rs:3:17"));
+
+    let runs = crate::check_in_memory(config.clone(), &mut [test(actual)]).unwrap();
+    assert!(matches!(runs[0].result, Ok(TestOk::Ok)));
+
+    // Blessed outputs are handed back instead of being left on disk.
+    let mut tests = [test(b"wrong")];
+    let bless = Config {
+        output_conflict_handling: crate::bless_output_files,
+        ..config.clone()
+    };
+    let runs = crate::check_in_memory(bless.clone(), &mut tests).unwrap();
+    assert!(matches!(runs[0].result, Ok(TestOk::Ok)));
+    assert_eq!(tests[0].snapshots["stderr"], *actual);
+
+    // The files of a test whose name starts with another test's name are not mixed up.
+    let mut tests = [
+        test(b"wrong"),
+        crate::InMemoryTest {
+            path: PathBuf::from("lints/overflow.more.rs"),
+            ..test(b"wrong")
+        },
+    ];
+    let runs = crate::check_in_memory(bless, &mut tests).unwrap();
+    assert_eq!(runs.len(), 2);
+    for test in &tests {
+        assert_eq!(test.snapshots.keys().collect::<Vec<_>>(), ["stderr"]);
+    }
+    assert_eq!(
+        std::fs::read_dir(out_dir.join("in_memory"))
+            .unwrap()
+            .count(),
+        0
+    );
+
+    let absolute = crate::InMemoryTest {
+        path: out_dir.join("foo.rs"),
+        ..test(b"")
+    };
+    assert!(crate::check_in_memory(config, &mut [absolute]).is_err());
+}
+
+#[test]
+#[cfg(feature = "rustc")]
+fn needs_feature_reports_metadata_errors() {
+    use crate::dependencies::DependencyBuilder;
+    let s = r"
+//@needs-feature: foo
+fn main() {}
+    ";
+    let mut config = Config {
+        host: Some("x86_64-unknown-linux-gnu".into()),
+        target: Some("x86_64-unknown-linux-gnu".into()),
+        ..Config::rustc("unused")
+    };
+    let dependencies = DependencyBuilder {
+        crate_manifest_path: PathBuf::from("does_not_exist/Cargo.toml"),
+        ..DependencyBuilder::default()
+    };
+    let err = dependencies.enabled_features(&config).unwrap_err();
+    assert!(err.command.contains("does_not_exist"), "{}", err.command);
+    // The failure is cached, later calls do not run `cargo metadata` again.
+    let err = dependencies.enabled_features(&config).unwrap_err();
+    assert_eq!(err.command, "cargo metadata");
+    config
+        .comment_defaults
+        .base()
+        .set_custom("dependencies", dependencies);
+    config!(config = s);
+    // The test is neither run nor ignored, the error is reported instead.
+    assert!(config.config.ignore_reason(&config.comments, "").is_err());
+    assert!(!config.config.test_file_conditions(&config.comments, ""));
+}
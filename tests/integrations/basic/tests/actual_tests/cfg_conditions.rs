@@ -0,0 +1,5 @@
+//@check-pass
+//@only-cfg: any(unix, windows, target_family = "wasm")
+//@ignore-cfg: not(any(target_endian = "little", target_endian = "big"))
+
+fn main() {}
@@ -0,0 +1,5 @@
+//@ignore-cfg: all()
+
+fn main() {
+    this test is never run
+}
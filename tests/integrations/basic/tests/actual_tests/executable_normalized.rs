@@ -0,0 +1,8 @@
+use basic::add;
+
+//@run
+//@normalize-run-stdout: "[0-9]+" -> "NUMBER"
+
+fn main() {
+    println!("{}", add(20, 22));
+}
@@ -0,0 +1,9 @@
+use basic::add;
+
+//@run
+//@compare-run-stdout: unordered
+
+fn main() {
+    println!("{}", add(20, 22));
+    println!("{}", add(1, 2));
+}
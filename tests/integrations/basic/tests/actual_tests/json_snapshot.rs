@@ -0,0 +1,10 @@
+//@no-rustfix
+//@json-snapshot
+
+#![deny(warnings)]
+
+fn main() {
+    let mut x = 42;
+    //~^ ERROR: does not need to be mutable
+    println!("{x}");
+}
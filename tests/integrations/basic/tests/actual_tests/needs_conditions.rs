@@ -0,0 +1,6 @@
+//@check-pass
+//@needs-env: PATH
+//@needs-program: cargo
+//@needs-feature: alloc
+
+fn main() {}
@@ -0,0 +1,5 @@
+//@needs-env: UI_TEST_THIS_VARIABLE_IS_NEVER_SET
+
+fn main() {
+    this test is never run
+}
@@ -0,0 +1,5 @@
+//@needs-feature: extra
+
+fn main() {
+    this test is never run
+}
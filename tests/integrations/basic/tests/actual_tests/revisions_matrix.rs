@@ -0,0 +1,9 @@
+//@revisions-matrix: edition=[2018,2021] x opt=[O0,O3]
+//@check-pass
+//@[edition=2018] edition: 2018
+//@[opt=O3] compile-flags: -O
+
+fn main() {
+    let r#async = 0;
+    let _ = r#async;
+}
@@ -0,0 +1,5 @@
+//@max-rustc-version: 1.0
+
+fn main() {
+    this test is never run
+}
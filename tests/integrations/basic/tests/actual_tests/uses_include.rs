@@ -0,0 +1,6 @@
+//@include: common/included.ui
+
+#[cfg(not(included))]
+compile_error!("the included `compile-flags` were not applied");
+
+fn main() {}
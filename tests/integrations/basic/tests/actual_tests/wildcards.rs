@@ -0,0 +1,10 @@
+//@no-rustfix
+//@compare-stderr: wildcards
+
+#![deny(warnings)]
+
+fn main() {
+    let mut x = 42;
+    //~^ ERROR: does not need to be mutable
+    println!("{x}");
+}
@@ -0,0 +1,4 @@
+#[cfg(not(from_defaults))]
+compile_error!("the defaults of the directory were not applied");
+
+fn main() {}
@@ -40,6 +40,6 @@ fn main() -> ui_test::color_eyre::Result<()> {
         default_file_filter,
         default_per_file_config,
         // Avoid github actions, as these would end up showing up in `Cargo.stderr`
-        status_emitter::Text::verbose(),
+        status_emitter::Text::verbose().show_ignored_reasons(),
     )
 }